@@ -0,0 +1,162 @@
+//! Load generator for echo servers.
+
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub address: SocketAddr,
+    /// Connections open at the same time
+    pub connections: usize,
+    /// Payloads sent by each connection, one after the other
+    pub requests: usize,
+    pub payload_size: usize,
+}
+
+/// Results of a run. Latencies are measured from sending a payload to receiving all of it back.
+#[derive(Debug)]
+pub struct Report {
+    /// Bytes sent, and received back, by all the connections
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// Sorted from fastest to slowest
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn new(bytes: u64, elapsed: Duration, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        Self {
+            bytes,
+            elapsed,
+            latencies,
+        }
+    }
+
+    pub fn requests(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Latency under which `percentile`% of the requests completed.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percentile / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    pub fn max(&self) -> Duration {
+        self.latencies.last().copied().unwrap_or_default()
+    }
+
+    /// Payload bytes echoed per second
+    pub fn throughput(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests() as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} requests, {} bytes in {:.2?}",
+            self.requests(),
+            self.bytes,
+            self.elapsed
+        )?;
+        writeln!(
+            f,
+            "Throughput: {:.2} MiB/s, {:.0} requests/s",
+            self.throughput() / (1024.0 * 1024.0),
+            self.requests_per_second()
+        )?;
+        write!(
+            f,
+            "Latency: p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+            self.percentile(50.0),
+            self.percentile(99.0),
+            self.max()
+        )
+    }
+}
+
+/// Open all the connections, send every payload and check that it comes back unchanged.
+///
+/// Fails on the first connection error or mismatched echo.
+pub async fn run(options: &Options) -> anyhow::Result<Report> {
+    let start = Instant::now();
+    let mut connections = JoinSet::new();
+    for connection in 0..options.connections {
+        connections.spawn(run_connection(connection, options.clone()));
+    }
+
+    let mut latencies = Vec::with_capacity(options.connections * options.requests);
+    while let Some(result) = connections.join_next().await {
+        latencies.extend(result??);
+    }
+    let elapsed = start.elapsed();
+
+    let bytes = (latencies.len() * options.payload_size) as u64;
+    Ok(Report::new(bytes, elapsed, latencies))
+}
+
+async fn run_connection(connection: usize, options: Options) -> anyhow::Result<Vec<Duration>> {
+    let mut stream = TcpStream::connect(options.address)
+        .await
+        .with_context(|| format!("Connection {connection} could not connect"))?;
+    stream.set_nodelay(true)?;
+
+    let mut echoed = vec![0; options.payload_size];
+    let mut latencies = Vec::with_capacity(options.requests);
+    for request in 0..options.requests {
+        let payload = payload(connection, request, options.payload_size);
+        let sent_at = Instant::now();
+        // Write and read at the same time, so big payloads can't fill both socket buffers
+        let (mut reader, mut writer) = stream.split();
+        let (written, read) =
+            tokio::join!(writer.write_all(&payload), reader.read_exact(&mut echoed));
+        written?;
+        read.with_context(|| format!("Connection {connection} closed on request {request}"))?;
+        latencies.push(sent_at.elapsed());
+
+        if echoed != payload {
+            bail!("Connection {connection} got back different bytes on request {request}");
+        }
+    }
+    Ok(latencies)
+}
+
+/// Bytes that differ between connections and requests, so mixed up echoes are noticed.
+fn payload(connection: usize, request: usize, size: usize) -> Vec<u8> {
+    let seed = connection.wrapping_mul(31).wrapping_add(request);
+    (0..size)
+        .map(|position| (seed.wrapping_add(position) % 251) as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+        let report = Report::new(0, Duration::from_secs(1), latencies);
+
+        assert_eq!(report.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(report.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(report.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(report.max(), Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use tokio::net::lookup_host;
+
+use smoke_test::bench::{self, Options};
+
+/// Load test an echo server, checking that every payload comes back unchanged
+#[derive(Parser, Debug)]
+struct Args {
+    /// Address of the echo server
+    #[arg(default_value = "127.0.0.1:7")]
+    address: String,
+
+    /// Connections open at the same time
+    #[arg(short, long, default_value_t = 10)]
+    connections: usize,
+
+    /// Payloads sent by each connection
+    #[arg(short = 'n', long, default_value_t = 1000)]
+    requests: usize,
+
+    /// Size of every payload, in bytes
+    #[arg(short = 's', long, default_value_t = 1024)]
+    payload_size: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.payload_size == 0 {
+        bail!("Payload size must be at least 1 byte");
+    }
+    let address = lookup_host(&args.address)
+        .await?
+        .next()
+        .with_context(|| format!("Could not resolve {}", args.address))?;
+
+    let options = Options {
+        address,
+        connections: args.connections,
+        requests: args.requests,
+        payload_size: args.payload_size,
+    };
+    let report = bench::run(&options).await?;
+    println!("{report}");
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+
+use protohackers_core::metrics::Metered;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// The 95 printable ASCII characters, in the order of the RFC example
+const CHARACTERS: &[u8] = b"!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~ ";
+const LINE_LENGTH: usize = 72;
+
+/// The `index`th line of the RFC 864 pattern: 72 characters, each line starting one
+/// character further than the previous one.
+pub(crate) fn line(index: usize) -> Vec<u8> {
+    let mut line: Vec<u8> = (0..LINE_LENGTH)
+        .map(|position| CHARACTERS[(index + position) % CHARACTERS.len()])
+        .collect();
+    line.extend_from_slice(b"\r\n");
+    line
+}
+
+/// Send lines of characters until the client closes the connection, ignoring what it sends.
+pub(crate) async fn chargen_tcp(
+    stream: TcpStream,
+    address: SocketAddr,
+    max_bytes: Option<u64>,
+) -> anyhow::Result<()> {
+    let (reader, writer) = stream.into_split();
+    let mut reader = Metered::new(reader);
+    let mut writer = Metered::new(writer);
+    let mut sent = 0u64;
+
+    // Whatever the client sends is ignored. It only matters when it closes the connection.
+    let closed = async {
+        let mut buffer = [0; 1024];
+        while let Ok(1..) = reader.read(&mut buffer).await {}
+    };
+    tokio::pin!(closed);
+
+    for index in 0.. {
+        let mut line = line(index);
+        if let Some(max_bytes) = max_bytes {
+            if sent >= max_bytes {
+                info!("{address} Reached the maximum number of bytes");
+                break;
+            }
+            line.truncate((max_bytes - sent).min(line.len() as u64) as usize);
+        }
+        tokio::select! {
+            _ = &mut closed => break,
+            written = writer.write_all(&line) => {
+                if written.is_err() {
+                    // Client went away while we were writing
+                    break;
+                }
+                sent += line.len() as u64;
+            }
+        }
+    }
+    let _ = writer.shutdown().await;
+    info!("{address} Sent {sent} bytes");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line() {
+        assert_eq!(
+            &line(0)[..],
+            b"!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefgh\r\n"
+        );
+    }
+}
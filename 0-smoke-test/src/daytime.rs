@@ -0,0 +1,34 @@
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Utc};
+use protohackers_core::metrics::Metered;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// Human readable date and time, in the format of the RFC 867 example.
+pub(crate) fn daytime(now: DateTime<Utc>) -> String {
+    now.format("%A, %B %-d, %Y %H:%M:%S-UTC\r\n").to_string()
+}
+
+/// Send the current date and time and close the connection.
+pub(crate) async fn daytime_tcp(stream: TcpStream, address: SocketAddr) -> anyhow::Result<()> {
+    let mut stream = Metered::new(stream);
+    let daytime = daytime(Utc::now());
+    stream.write_all(daytime.as_bytes()).await?;
+    stream.shutdown().await?;
+    debug!("{address} Sent {}", daytime.trim_end());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn test_daytime() {
+        let now = Utc.with_ymd_and_hms(1982, 2, 22, 17, 37, 43).unwrap();
+        assert_eq!(daytime(now), "Monday, February 22, 1982 17:37:43-UTC\r\n");
+    }
+}
@@ -0,0 +1,31 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use protohackers_core::metrics::Metered;
+use protohackers_core::timeout::timeout;
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+/// Read and throw away everything until the client closes the connection.
+pub(crate) async fn discard_tcp(
+    stream: TcpStream,
+    address: SocketAddr,
+    idle_timeout: Option<Duration>,
+) -> anyhow::Result<()> {
+    let mut stream = Metered::new(stream);
+    let mut buffer = [0; 4096];
+    let mut discarded = 0u64;
+    loop {
+        let Ok(read) = timeout(idle_timeout, stream.read(&mut buffer)).await else {
+            info!("{address} Closing idle connection");
+            break;
+        };
+        let len = read?;
+        if len == 0 {
+            break;
+        }
+        discarded += len as u64;
+    }
+    info!("{address} Discarded {discarded} bytes");
+    Ok(())
+}
@@ -0,0 +1,55 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use protohackers_core::metrics::Metered;
+use protohackers_core::timeout::timeout;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Echo everything back until the client closes its side of the connection, then close ours.
+pub(crate) async fn echo_tcp(
+    stream: TcpStream,
+    address: SocketAddr,
+    idle_timeout: Option<Duration>,
+    max_bytes: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut stream = Metered::new(stream);
+    let mut echoed = 0u64;
+    let result = echo(&mut stream, &mut echoed, address, idle_timeout, max_bytes).await;
+    // Logged even when the connection failed half way
+    info!("{address} Echoed {echoed} bytes");
+    result
+}
+
+async fn echo(
+    stream: &mut Metered<TcpStream>,
+    echoed: &mut u64,
+    address: SocketAddr,
+    idle_timeout: Option<Duration>,
+    max_bytes: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut buffer = [0; 4096];
+    loop {
+        let limit = match max_bytes {
+            Some(max_bytes) if *echoed >= max_bytes => {
+                info!("{address} Reached the maximum number of bytes");
+                break;
+            }
+            Some(max_bytes) => buffer.len().min((max_bytes - *echoed) as usize),
+            None => buffer.len(),
+        };
+        let Ok(read) = timeout(idle_timeout, stream.read(&mut buffer[..limit])).await else {
+            info!("{address} Closing idle connection");
+            break;
+        };
+        let len = read?;
+        if len == 0 {
+            // Client is done sending. Everything it sent was already echoed back.
+            break;
+        }
+        stream.write_all(&buffer[..len]).await?;
+        *echoed += len as u64;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
@@ -0,0 +1,11 @@
+#[macro_use]
+extern crate log;
+
+pub mod bench;
+mod chargen;
+mod daytime;
+mod discard;
+mod echo;
+pub mod qotd;
+pub mod server;
+pub mod service;
@@ -1,44 +1,120 @@
 #[macro_use]
 extern crate log;
 
-use std::io;
-use std::net::Ipv4Addr;
+use std::path::PathBuf;
 
-use clap::Parser;
-use pretty_env_logger::env_logger::Env;
-use tokio::io::copy;
-use tokio::net::TcpListener;
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use tokio::task::JoinSet;
+
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::shutdown::Shutdown;
+use protohackers_core::{cli, logging, metrics};
+use smoke_test::qotd::{parse_quotes, MAX_QUOTE_LENGTH};
+use smoke_test::server::Server;
+use smoke_test::service::Service;
 
 #[derive(Parser, Debug)]
 #[command(about)]
 struct Args {
-    /// Host address to bind to
-    #[arg(default_value_t = Ipv4Addr::from(0))]
-    pub host: Ipv4Addr,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    server: ServerArgs,
+
+    /// Close echo and chargen TCP connections after sending this many bytes. Unlimited when not given
+    #[arg(long, global = true)]
+    max_bytes: Option<u64>,
+
+    /// Only serve over TCP, not UDP
+    #[arg(long, global = true)]
+    no_udp: bool,
+
+    /// File with the quotes served by qotd, separated by blank lines
+    #[arg(long, global = true)]
+    quotes: Option<PathBuf>,
+}
 
-    /// Port to listen
-    #[arg(default_value_t = 7)]
-    pub port: u16,
+/// Service to run on `--port`. Echo when not given.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// RFC 862 echo
+    Echo,
+    /// RFC 863 discard
+    Discard,
+    /// RFC 864 character generator
+    Chargen,
+    /// RFC 867 daytime
+    Daytime,
+    /// RFC 865 quote of the day
+    Qotd,
+    /// Every service at once, each on its standard port
+    All {
+        /// Added to the standard ports, which are privileged
+        #[arg(long, default_value_t = 0)]
+        port_offset: u16,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args: Args = Args::parse();
-
-    pretty_env_logger::env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .init();
-
-    let bind_address = (args.host, args.port);
-    let listener = TcpListener::bind(bind_address).await?;
-    info!("Starting ECHO server at {}", listener.local_addr().unwrap());
-
-    loop {
-        let (mut stream, address) = listener.accept().await?;
-        info!("New connection from {address}");
-        tokio::spawn(async move {
-            let (mut reader, mut writer) = stream.split();
-            copy(&mut reader, &mut writer).await?;
-            Ok::<_, io::Error>(())
-        });
+    let args: Args = cli::parse(7);
+    logging::init(&args.server.verbose)?;
+    if let Some(address) = args.server.metrics_address() {
+        metrics::serve(address).await?;
     }
+
+    let quotes = match &args.quotes {
+        None => Vec::new(),
+        Some(path) => {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read quotes from {}", path.display()))?;
+            let quotes = parse_quotes(&contents);
+            if quotes.iter().any(|quote| quote.len() > MAX_QUOTE_LENGTH) {
+                warn!("Quotes longer than {MAX_QUOTE_LENGTH} bytes are cut short over UDP");
+            }
+            quotes
+        }
+    };
+
+    let services = match args.command {
+        None | Some(Command::Echo) => vec![(Service::Echo, args.server.port)],
+        Some(Command::Discard) => vec![(Service::Discard, args.server.port)],
+        Some(Command::Chargen) => vec![(Service::Chargen, args.server.port)],
+        Some(Command::Daytime) => vec![(Service::Daytime, args.server.port)],
+        Some(Command::Qotd) => vec![(Service::Qotd, args.server.port)],
+        Some(Command::All { port_offset }) => Service::ALL
+            .iter()
+            .map(|&service| {
+                let port = service
+                    .standard_port()
+                    .checked_add(port_offset)
+                    .context("Port offset too large")?;
+                Ok((service, port))
+            })
+            .collect::<anyhow::Result<_>>()?,
+    };
+
+    let shutdown = Shutdown::new();
+    shutdown.trigger_on_signal();
+    let mut servers = JoinSet::new();
+    for (service, port) in services {
+        let mut server = Server::new((args.server.host, port))
+            .await
+            .with_context(|| format!("Could not bind {service} to port {port}"))?
+            .with_service(service)
+            .with_config(args.server.server_config())
+            .with_max_bytes(args.max_bytes)
+            .with_quotes(quotes.clone())
+            .with_shutdown(shutdown.clone());
+        if args.no_udp {
+            server = server.without_udp();
+        }
+        info!("Starting {service} server at {}", server.local_addr()?);
+        servers.spawn(server.run());
+    }
+    while servers.join_next().await.is_some() {}
+
+    Ok(())
 }
@@ -0,0 +1,70 @@
+use std::net::SocketAddr;
+
+use chrono::Utc;
+use protohackers_core::metrics::Metered;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+/// RFC 865 asks to keep quotes shorter than this
+pub const MAX_QUOTE_LENGTH: usize = 512;
+
+/// Served when no quotes file is given
+pub(crate) const DEFAULT_QUOTES: &[&str] = &[
+    "Be conservative in what you do, be liberal in what you accept from others.\r\n  Jon Postel",
+    "The network is reliable.\r\n  First fallacy of distributed computing",
+    "There is nothing so useless as doing efficiently that which should not be done at all.\r\n  Peter Drucker",
+    "Simplicity is prerequisite for reliability.\r\n  Edsger W. Dijkstra",
+];
+
+/// Split the contents of a quotes file into quotes. Quotes are separated by blank lines.
+pub fn parse_quotes(contents: &str) -> Vec<String> {
+    contents
+        .replace("\r\n", "\n")
+        .split("\n\n")
+        .map(|quote| quote.trim().replace('\n', "\r\n"))
+        .filter(|quote| !quote.is_empty())
+        .collect()
+}
+
+/// Every day of the year gets its own quote, in turn.
+pub(crate) fn quote_of_the_day(quotes: &[String], days_since_epoch: i64) -> &str {
+    &quotes[days_since_epoch.rem_euclid(quotes.len() as i64) as usize]
+}
+
+pub(crate) fn today() -> i64 {
+    Utc::now().timestamp().div_euclid(24 * 60 * 60)
+}
+
+/// Send the quote of the day and close the connection.
+pub(crate) async fn qotd_tcp(
+    stream: TcpStream,
+    address: SocketAddr,
+    quotes: &[String],
+) -> anyhow::Result<()> {
+    let mut stream = Metered::new(stream);
+    let quote = quote_of_the_day(quotes, today());
+    stream.write_all(quote.as_bytes()).await?;
+    stream.write_all(b"\r\n").await?;
+    stream.shutdown().await?;
+    debug!("{address} Sent the quote of the day");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quotes() {
+        let quotes = parse_quotes("First line\nsecond line\n\n\n  Another quote  \r\n\r\n");
+        assert_eq!(quotes, ["First line\r\nsecond line", "Another quote"]);
+    }
+
+    #[test]
+    fn test_quote_of_the_day() {
+        let quotes = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(quote_of_the_day(&quotes, 0), "a");
+        assert_eq!(quote_of_the_day(&quotes, 1), "b");
+        assert_eq!(quote_of_the_day(&quotes, 2), "a");
+    }
+}
@@ -0,0 +1,137 @@
+use std::io;
+use std::net::SocketAddr;
+
+use protohackers_core::metrics::{BYTES_RECEIVED, BYTES_SENT};
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
+use tokio::net::{ToSocketAddrs, UdpSocket};
+
+use crate::qotd::DEFAULT_QUOTES;
+use crate::service::{Service, Settings};
+
+/// Largest payload of a UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65_507;
+
+/// One of the RFC simple services, over TCP and UDP on the same port. Echo by default.
+pub struct Server {
+    server: protohackers_core::server::Server,
+    udp_socket: Option<UdpSocket>,
+    service: Service,
+    max_bytes: Option<u64>,
+    quotes: Vec<String>,
+}
+
+impl Server {
+    pub async fn new(bind_address: impl ToSocketAddrs) -> io::Result<Self> {
+        let server = protohackers_core::server::Server::bind(bind_address).await?;
+        let udp_socket = UdpSocket::bind(server.local_addr()).await?;
+        Ok(Self {
+            server,
+            udp_socket: Some(udp_socket),
+            service: Service::Echo,
+            max_bytes: None,
+            quotes: DEFAULT_QUOTES
+                .iter()
+                .map(|quote| quote.to_string())
+                .collect(),
+        })
+    }
+
+    pub fn with_service(mut self, service: Service) -> Self {
+        self.service = service;
+        self
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.server = self.server.with_config(config);
+        self
+    }
+
+    /// Close echo and chargen TCP connections after `max_bytes` were sent.
+    pub fn with_max_bytes(mut self, max_bytes: Option<u64>) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Quotes served by qotd, instead of the built-in ones. Ignored when empty.
+    pub fn with_quotes(mut self, quotes: Vec<String>) -> Self {
+        if !quotes.is_empty() {
+            self.quotes = quotes;
+        }
+        self
+    }
+
+    /// Only serve over TCP.
+    pub fn without_udp(mut self) -> Self {
+        self.udp_socket = None;
+        self
+    }
+
+    /// Stop this server together with the others sharing `shutdown`.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.server = self.server.with_shutdown(shutdown);
+        self
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown_handle()
+    }
+
+    pub fn service(&self) -> Service {
+        self.service
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.server.local_addr())
+    }
+
+    pub async fn run(self) {
+        let service = self.service;
+        let settings = Settings {
+            idle_timeout: self.server.config().idle_timeout,
+            max_bytes: self.max_bytes,
+            quotes: self.quotes.into(),
+        };
+        if let Some(udp_socket) = self.udp_socket {
+            tokio::spawn(serve_udp(
+                udp_socket,
+                service,
+                settings.clone(),
+                self.server.shutdown_handle(),
+            ));
+        }
+
+        self.server
+            .run(move |stream, address| service.handle_tcp(stream, address, settings.clone()))
+            .await;
+    }
+}
+
+/// Answer every datagram that `service` has a reply for, back to where it came from.
+async fn serve_udp(socket: UdpSocket, service: Service, settings: Settings, shutdown: Shutdown) {
+    info!(
+        "Serving {service} over UDP at {}",
+        socket.local_addr().unwrap()
+    );
+    let mut buffer = vec![0; MAX_DATAGRAM_SIZE];
+    for sequence in 0.. {
+        let (len, address) = tokio::select! {
+            received = socket.recv_from(&mut buffer) => match received {
+                Ok(received) => received,
+                Err(err) => {
+                    warn!("Failed to receive datagram: {err}");
+                    continue;
+                }
+            },
+            _ = shutdown.wait() => return,
+        };
+        BYTES_RECEIVED.inc_by(len as u64);
+        let Some(reply) = service.reply_udp(&buffer[..len], sequence, &settings) else {
+            continue;
+        };
+        match socket.send_to(&reply, address).await {
+            Ok(sent) => BYTES_SENT.inc_by(sent as u64),
+            Err(err) => warn!("{address} Failed to answer datagram: {err}"),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::net::TcpStream;
+
+use crate::chargen::{self, chargen_tcp};
+use crate::daytime::{daytime, daytime_tcp};
+use crate::discard::discard_tcp;
+use crate::echo::echo_tcp;
+use crate::qotd::{qotd_tcp, quote_of_the_day, today, MAX_QUOTE_LENGTH};
+
+/// The RFC "simple services", each on its own well known port.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Service {
+    /// RFC 862: send back everything received
+    Echo,
+    /// RFC 863: throw away everything received
+    Discard,
+    /// RFC 864: send characters until the client goes away
+    Chargen,
+    /// RFC 867: send the current date and time
+    Daytime,
+    /// RFC 865: send a short message
+    Qotd,
+}
+
+impl Service {
+    pub const ALL: [Service; 5] = [
+        Service::Echo,
+        Service::Discard,
+        Service::Chargen,
+        Service::Daytime,
+        Service::Qotd,
+    ];
+
+    pub fn standard_port(self) -> u16 {
+        match self {
+            Service::Echo => 7,
+            Service::Discard => 9,
+            Service::Chargen => 19,
+            Service::Daytime => 13,
+            Service::Qotd => 17,
+        }
+    }
+
+    pub(crate) async fn handle_tcp(
+        self,
+        stream: TcpStream,
+        address: SocketAddr,
+        settings: Settings,
+    ) -> anyhow::Result<()> {
+        match self {
+            Service::Echo => {
+                echo_tcp(stream, address, settings.idle_timeout, settings.max_bytes).await
+            }
+            Service::Discard => discard_tcp(stream, address, settings.idle_timeout).await,
+            Service::Chargen => chargen_tcp(stream, address, settings.max_bytes).await,
+            Service::Daytime => daytime_tcp(stream, address).await,
+            Service::Qotd => qotd_tcp(stream, address, &settings.quotes).await,
+        }
+    }
+
+    /// Answer to a UDP `datagram`, if the service answers at all.
+    ///
+    /// `sequence` counts the datagrams received so far.
+    pub(crate) fn reply_udp(
+        self,
+        datagram: &[u8],
+        sequence: usize,
+        settings: &Settings,
+    ) -> Option<Vec<u8>> {
+        match self {
+            Service::Echo => Some(datagram.to_vec()),
+            Service::Discard => None,
+            Service::Chargen => {
+                // Up to 512 characters, starting one line further every datagram
+                Some((sequence..).flat_map(chargen::line).take(512).collect())
+            }
+            Service::Daytime => Some(daytime(Utc::now()).into_bytes()),
+            Service::Qotd => {
+                let mut quote = quote_of_the_day(&settings.quotes, today())
+                    .as_bytes()
+                    .to_vec();
+                quote.truncate(MAX_QUOTE_LENGTH);
+                Some(quote)
+            }
+        }
+    }
+}
+
+impl Display for Service {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Service::Echo => "echo",
+            Service::Discard => "discard",
+            Service::Chargen => "chargen",
+            Service::Daytime => "daytime",
+            Service::Qotd => "qotd",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Options shared by every connection of a service.
+#[derive(Clone, Debug)]
+pub(crate) struct Settings {
+    pub idle_timeout: Option<Duration>,
+    pub max_bytes: Option<u64>,
+    pub quotes: Arc<[String]>,
+}
@@ -0,0 +1,42 @@
+use smoke_test::bench::{self, Options};
+use smoke_test::server::Server;
+use smoke_test::service::Service;
+
+#[tokio::test]
+async fn test_bench_echo_server() {
+    let server = Server::new("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let options = Options {
+        address,
+        connections: 8,
+        requests: 50,
+        payload_size: 16 * 1024,
+    };
+    let report = bench::run(&options).await.unwrap();
+
+    assert_eq!(report.requests(), 400);
+    assert_eq!(report.bytes, 400 * 16 * 1024);
+    assert!(report.percentile(50.0) <= report.percentile(99.0));
+    assert!(report.percentile(99.0) <= report.max());
+}
+
+#[tokio::test]
+async fn test_bench_detects_wrong_echo() {
+    // Chargen sends its own characters instead of the payload
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_service(Service::Chargen);
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let options = Options {
+        address,
+        connections: 1,
+        requests: 1,
+        payload_size: 16,
+    };
+    assert!(bench::run(&options).await.is_err());
+}
@@ -0,0 +1,228 @@
+use std::net::SocketAddr;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use protohackers_core::shutdown::Shutdown;
+use smoke_test::server::Server;
+use smoke_test::service::Service;
+
+async fn start_server() -> SocketAddr {
+    start_service(Service::Echo).await
+}
+
+async fn start_service(service: Service) -> SocketAddr {
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_service(service);
+    let addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move { server.run().await });
+
+    addr
+}
+
+/// Send `payload`, half-close the connection and read everything echoed back.
+async fn echo(server: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let stream = TcpStream::connect(server).await.unwrap();
+    let (mut reader, mut writer) = stream.into_split();
+
+    // Write while reading, so big payloads don't fill both sides' buffers
+    let payload = payload.to_vec();
+    let write = tokio::spawn(async move {
+        writer.write_all(&payload).await.unwrap();
+        writer.shutdown().await.unwrap();
+    });
+    let mut echoed = Vec::new();
+    reader.read_to_end(&mut echoed).await.unwrap();
+    write.await.unwrap();
+    echoed
+}
+
+#[tokio::test]
+async fn test_echo() {
+    let server = start_server().await;
+    let mut stream = TcpStream::connect(server).await.unwrap();
+
+    stream.write_all(b"Hello\n").await.unwrap();
+    let mut buffer = [0; 6];
+    stream.read_exact(&mut buffer).await.unwrap();
+    assert_eq!(&buffer, b"Hello\n");
+}
+
+#[tokio::test]
+async fn test_half_close() {
+    let server = start_server().await;
+    let mut stream = TcpStream::connect(server).await.unwrap();
+
+    stream.write_all(b"last words").await.unwrap();
+    stream.shutdown().await.unwrap();
+
+    // Everything sent before the half-close comes back, then the server closes too
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, b"last words");
+}
+
+#[tokio::test]
+async fn test_large_payload() {
+    let server = start_server().await;
+    let payload: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+    assert_eq!(echo(server, &payload).await, payload);
+}
+
+#[tokio::test]
+async fn test_binary_data() {
+    let server = start_server().await;
+    // Not valid UTF-8, with NULs and a lone continuation byte
+    let payload = [0x00, 0xff, 0xfe, 0x80, 0x00, 0xc3, 0x28, 0x0a, 0x0d];
+
+    assert_eq!(echo(server, &payload).await, payload);
+}
+
+#[tokio::test]
+async fn test_many_concurrent_clients() {
+    let server = start_server().await;
+
+    let clients: Vec<_> = (0..100u32)
+        .map(|client| {
+            tokio::spawn(async move {
+                let payload = client.to_be_bytes().repeat(1024);
+                assert_eq!(echo(server, &payload).await, payload);
+            })
+        })
+        .collect();
+    for client in clients {
+        client.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_max_bytes() {
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_max_bytes(Some(4));
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    stream.write_all(b"too long").await.unwrap();
+    let mut echoed = Vec::new();
+    stream.read_to_end(&mut echoed).await.unwrap();
+    assert_eq!(echoed, b"too ");
+}
+
+#[tokio::test]
+async fn test_udp_echo() {
+    let server = start_server().await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    socket.send_to(b"\x00datagram\xff", server).await.unwrap();
+    let mut buffer = [0; 64];
+    let (len, from) = socket.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..len], b"\x00datagram\xff");
+    assert_eq!(from, server);
+}
+
+#[tokio::test]
+async fn test_discard() {
+    let server = start_service(Service::Discard).await;
+
+    assert!(echo(server, b"into the void").await.is_empty());
+}
+
+#[tokio::test]
+async fn test_chargen() {
+    let server = start_service(Service::Chargen).await;
+    let mut stream = TcpStream::connect(server).await.unwrap();
+
+    let mut lines = [0; 148];
+    stream.read_exact(&mut lines).await.unwrap();
+    assert_eq!(
+        &lines[..],
+        &b"!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefgh\r\n\
+        \"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghi\r\n"[..]
+    );
+}
+
+#[tokio::test]
+async fn test_chargen_max_bytes() {
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_service(Service::Chargen)
+        .with_max_bytes(Some(100));
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let mut received = Vec::new();
+    stream.read_to_end(&mut received).await.unwrap();
+    assert_eq!(received.len(), 100);
+}
+
+#[tokio::test]
+async fn test_daytime() {
+    let server = start_service(Service::Daytime).await;
+    let mut stream = TcpStream::connect(server).await.unwrap();
+
+    let mut daytime = String::new();
+    stream.read_to_string(&mut daytime).await.unwrap();
+    assert!(daytime.ends_with("-UTC\r\n"), "{daytime:?}");
+}
+
+#[tokio::test]
+async fn test_qotd() {
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_service(Service::Qotd)
+        .with_quotes(vec!["Only quote".to_string()]);
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let mut quote = String::new();
+    stream.read_to_string(&mut quote).await.unwrap();
+    assert_eq!(quote, "Only quote\r\n");
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.send_to(b"", address).await.unwrap();
+    let mut buffer = [0; 512];
+    let (len, _) = socket.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(&buffer[..len], b"Only quote");
+}
+
+#[tokio::test]
+async fn test_udp_chargen() {
+    let server = start_service(Service::Chargen).await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    socket.send_to(b"", server).await.unwrap();
+    let mut buffer = [0; 1024];
+    let (len, _) = socket.recv_from(&mut buffer).await.unwrap();
+    assert_eq!(len, 512);
+    assert!(buffer[..len].starts_with(b"!\"#$%&"));
+}
+
+#[tokio::test]
+async fn test_shared_shutdown() {
+    let shutdown = Shutdown::new();
+    let mut servers = Vec::new();
+    for service in Service::ALL {
+        let server = Server::new("127.0.0.1:0")
+            .await
+            .unwrap()
+            .with_service(service)
+            .with_shutdown(shutdown.clone());
+        servers.push(tokio::spawn(server.run()));
+    }
+
+    shutdown.trigger();
+    for server in servers {
+        server.await.unwrap();
+    }
+}
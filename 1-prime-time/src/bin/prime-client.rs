@@ -0,0 +1,63 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use tokio::net::lookup_host;
+
+use prime_time::client;
+
+/// Ask a prime-time server whether numbers are prime, one per line of the input
+#[derive(Parser, Debug)]
+struct Args {
+    /// Address of the prime-time server
+    #[arg(default_value = "127.0.0.1:9901")]
+    address: String,
+
+    /// File with the numbers. Read from stdin when not given
+    #[arg(short, long)]
+    file: Option<PathBuf>,
+
+    /// Requests sent before waiting for their responses
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pipeline: usize,
+
+    /// Send malformed requests instead, checking that each gets an error back and the
+    /// connection closed
+    #[arg(long)]
+    fuzz: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let address = lookup_host(&args.address)
+        .await?
+        .next()
+        .with_context(|| format!("Could not resolve {}", args.address))?;
+
+    if args.fuzz {
+        let report = client::fuzz(address, client::DEFAULT_FUZZ_TIMEOUT).await?;
+        println!("{report}");
+        if !report.is_success() {
+            bail!("The server mishandled some malformed requests");
+        }
+        return Ok(());
+    }
+
+    let input = match &args.file {
+        Some(file) => std::fs::read_to_string(file)
+            .with_context(|| format!("Could not read {}", file.display()))?,
+        None => {
+            let mut input = String::new();
+            std::io::stdin().read_to_string(&mut input)?;
+            input
+        }
+    };
+    let numbers = client::parse_numbers(&input)?;
+    let primes = client::is_prime(address, &numbers, args.pipeline).await?;
+    for (number, prime) in numbers.iter().zip(primes) {
+        println!("{number}: {}", if prime { "prime" } else { "not prime" });
+    }
+    Ok(())
+}
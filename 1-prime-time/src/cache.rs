@@ -0,0 +1,115 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+use num_bigint::BigInt;
+use protohackers_core::metrics::Counter;
+
+pub(crate) static CACHE_HITS: Counter = Counter::new(
+    "prime_time_cache_hits_total",
+    "Primality results found in the cache",
+);
+pub(crate) static CACHE_MISSES: Counter = Counter::new(
+    "prime_time_cache_misses_total",
+    "Primality results computed because they were not in the cache",
+);
+
+/// Bits of the largest number cached. With keys of at most 512 bytes, a cache of the default
+/// size stays within a few MiB.
+pub const MAX_CACHED_BITS: u64 = 4096;
+
+/// Primality of the numbers asked about most recently, shared by every connection.
+///
+/// Numbers over [`MAX_CACHED_BITS`] are not cached, as request lines of a megabyte could fill
+/// it with gigabytes of keys.
+pub struct PrimalityCache {
+    /// `None` when the cache is disabled
+    entries: Option<Mutex<LruCache<BigInt, bool>>>,
+}
+
+impl PrimalityCache {
+    /// Cache of up to `capacity` numbers. 0 disables it.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// The cached primality of `number`, or the result of `is_prime` which is then cached.
+    pub fn get_or_compute(&self, number: &BigInt, is_prime: impl FnOnce(&BigInt) -> bool) -> bool {
+        let Some(entries) = &self.entries else {
+            return is_prime(number);
+        };
+        if number.bits() > MAX_CACHED_BITS {
+            return is_prime(number);
+        }
+        if let Some(&prime) = entries.lock().unwrap().get(number) {
+            CACHE_HITS.inc();
+            return prime;
+        }
+        CACHE_MISSES.inc();
+        // Not holding the lock: other connections may look up other numbers meanwhile
+        let prime = is_prime(number);
+        entries.lock().unwrap().put(number.clone(), prime);
+        prime
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_cache() {
+        let computed = Cell::new(0);
+        let is_even = |number: &BigInt| {
+            computed.set(computed.get() + 1);
+            number % 2 == BigInt::from(0)
+        };
+        let cache = PrimalityCache::new(2);
+        let (one, two, three) = (BigInt::from(1), BigInt::from(2), BigInt::from(3));
+
+        assert!(!cache.get_or_compute(&one, is_even));
+        assert!(cache.get_or_compute(&two, is_even));
+        assert!(!cache.get_or_compute(&one, is_even));
+        assert_eq!(computed.get(), 2);
+
+        // Evicts 2, the least recently used
+        assert!(!cache.get_or_compute(&three, is_even));
+        assert!(!cache.get_or_compute(&one, is_even));
+        assert_eq!(computed.get(), 3);
+        assert!(cache.get_or_compute(&two, is_even));
+        assert_eq!(computed.get(), 4);
+    }
+
+    #[test]
+    fn test_huge_numbers_not_cached() {
+        let computed = Cell::new(0);
+        let cache = PrimalityCache::new(2);
+        let huge = BigInt::from(1) << MAX_CACHED_BITS;
+        for _ in 0..3 {
+            cache.get_or_compute(&huge, |_| {
+                computed.set(computed.get() + 1);
+                false
+            });
+        }
+        assert_eq!(computed.get(), 3);
+        assert!(cache.entries.unwrap().into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let computed = Cell::new(0);
+        let cache = PrimalityCache::new(0);
+        for _ in 0..3 {
+            cache.get_or_compute(&BigInt::from(7), |_| {
+                computed.set(computed.get() + 1);
+                true
+            });
+        }
+        assert_eq!(computed.get(), 3);
+    }
+}
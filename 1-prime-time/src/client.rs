@@ -0,0 +1,242 @@
+//! Client for the line-delimited JSON protocol, and fuzzer of the server's error handling.
+
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::{json, Number};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+/// How long the fuzzer waits by default for the server to answer and close the connection
+pub const DEFAULT_FUZZ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A valid request, whose truncations are all malformed
+const VALID_REQUEST: &str = r#"{"method":"isPrime","number":7}"#;
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    method: Option<String>,
+    prime: Option<bool>,
+    error: Option<String>,
+}
+
+/// Numbers of `input`, one per line. Blank lines are skipped.
+pub fn parse_numbers(input: &str) -> anyhow::Result<Vec<Number>> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(line_number, line)| {
+            Number::from_str(line)
+                .map_err(|_| anyhow::anyhow!("Line {line_number}: `{line}` is not a number"))
+        })
+        .collect()
+}
+
+/// Ask the server at `address` whether each of `numbers` is prime, over a single connection.
+///
+/// Up to `pipeline` requests are sent before waiting for their responses, 1 waiting for each
+/// response before sending the next request.
+pub async fn is_prime(
+    address: SocketAddr,
+    numbers: &[Number],
+    pipeline: usize,
+) -> anyhow::Result<Vec<bool>> {
+    if pipeline == 0 {
+        bail!("At least 1 request must be in flight");
+    }
+    let stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("Could not connect to {address}"))?;
+    stream.set_nodelay(true)?;
+    let (reader, writer) = stream.into_split();
+
+    // Requests are sent from their own task while the responses are read. With a window
+    // larger than the socket buffers, the server would otherwise stop reading requests
+    // until its responses are read, while the client is still sending them.
+    let window = Arc::new(Semaphore::new(pipeline.min(numbers.len())));
+    let mut sender = JoinSet::new();
+    sender.spawn(send_requests(writer, numbers.to_vec(), Arc::clone(&window)));
+
+    let mut lines = BufReader::new(reader).lines();
+    let mut results = Vec::with_capacity(numbers.len());
+    for number in numbers {
+        let Some(line) = lines.next_line().await? else {
+            bail!("Server closed the connection before answering about {number}");
+        };
+        let response: Response = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid response about {number}: {line}"))?;
+        match response {
+            Response {
+                error: Some(error), ..
+            } => bail!("Server refused {number}: {error}"),
+            Response {
+                method: Some(method),
+                prime: Some(prime),
+                ..
+            } if method == "isPrime" => results.push(prime),
+            _ => bail!("Invalid response about {number}: {line}"),
+        }
+        window.add_permits(1);
+    }
+    if let Some(sent) = sender.join_next().await {
+        sent??;
+    }
+    Ok(results)
+}
+
+/// Send an `isPrime` request for each of `numbers`, taking a permit of `window` for each.
+async fn send_requests(
+    writer: OwnedWriteHalf,
+    numbers: Vec<Number>,
+    window: Arc<Semaphore>,
+) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    for number in numbers {
+        let permit = match window.try_acquire() {
+            Ok(permit) => permit,
+            Err(_) => {
+                // Out of the server's hands until the requests buffered so far are sent
+                writer.flush().await?;
+                window.acquire().await?
+            }
+        };
+        permit.forget();
+        let mut request = json!({"method": "isPrime", "number": number}).to_string();
+        request.push('\n');
+        writer.write_all(request.as_bytes()).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Malformed requests, each of which should get an error back and the connection closed.
+pub fn fuzz_cases() -> Vec<String> {
+    let mut cases: Vec<String> = [
+        "",
+        "not json",
+        "{}",
+        "[]",
+        "null",
+        "7",
+        r#""isPrime""#,
+        r#"{"number":7}"#,
+        r#"{"method":"isPrime"}"#,
+        r#"{"method":"isPrime","number":"7"}"#,
+        r#"{"method":"isPrime","number":null}"#,
+        r#"{"method":"isPrime","number":[7]}"#,
+        r#"{"method":"isPrime","number":007}"#,
+        r#"{"method":"isPrime","number":+7}"#,
+        r#"{"method":"isPrime","number":NaN}"#,
+        r#"{"method":"isPrime","number":7,}"#,
+        r#"{"method":"isPrime","number":7}}"#,
+        r#"{"method":"isPrime","number":7} 8"#,
+        r#"{"method":"notPrime","number":7}"#,
+        r#"{"method":"IsPrime","number":7}"#,
+        r#"{"method":7,"number":7}"#,
+        r#"{'method':'isPrime','number':7}"#,
+    ]
+    .map(String::from)
+    .into();
+    // Every truncation of a valid request
+    cases.extend((1..VALID_REQUEST.len()).map(|length| VALID_REQUEST[..length].to_string()));
+    cases
+}
+
+/// Outcome of sending every case of [`fuzz_cases`].
+#[derive(Debug, Default)]
+pub struct FuzzReport {
+    pub passed: usize,
+    /// Cases the server didn't handle as expected, with what went wrong
+    pub failures: Vec<(String, String)>,
+}
+
+impl FuzzReport {
+    pub fn is_success(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for FuzzReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (case, failure) in &self.failures {
+            writeln!(f, "FAILED {case:?}: {failure}")?;
+        }
+        write!(f, "{} passed, {} failed", self.passed, self.failures.len())
+    }
+}
+
+/// Send each malformed request on its own connection, after a valid one, and check that the
+/// server answers the valid request, then an error object, then closes the connection, all
+/// within `patience`.
+pub async fn fuzz(address: SocketAddr, patience: Duration) -> anyhow::Result<FuzzReport> {
+    let mut report = FuzzReport::default();
+    for case in fuzz_cases() {
+        let outcome = timeout(patience, fuzz_case(address, &case))
+            .await
+            .unwrap_or_else(|_| Err(anyhow::anyhow!("Timed out")));
+        match outcome {
+            Ok(()) => report.passed += 1,
+            Err(err) => report.failures.push((case, format!("{err:#}"))),
+        }
+    }
+    Ok(report)
+}
+
+async fn fuzz_case(address: SocketAddr, case: &str) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(address)
+        .await
+        .with_context(|| format!("Could not connect to {address}"))?;
+    // Nothing is sent after the malformed request, so no unread data makes the server reset
+    // the connection instead of closing it
+    let request = format!("{VALID_REQUEST}\n{case}\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let response = lines.next_line().await?.context("No response")?;
+    let response: Response = serde_json::from_str(&response)
+        .with_context(|| format!("Invalid response to the valid request: {response}"))?;
+    if response.prime != Some(true) {
+        bail!("Wrong response to the valid request: {response:?}");
+    }
+
+    let error = lines.next_line().await?.context("No error response")?;
+    match serde_json::from_str::<Response>(&error) {
+        Ok(Response { error: Some(_), .. }) => {}
+        _ => bail!("Not an error response: {error}"),
+    }
+
+    if let Some(line) = lines.next_line().await? {
+        bail!("Connection left open, then got: {line}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numbers() {
+        let numbers =
+            parse_numbers("7\n\n  -3 \n5.0\n170141183460469231731687303715884105727\n").unwrap();
+        let numbers: Vec<String> = numbers.iter().map(Number::to_string).collect();
+        assert_eq!(
+            numbers,
+            ["7", "-3", "5.0", "170141183460469231731687303715884105727"]
+        );
+
+        let err = parse_numbers("7\nseven\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: `seven` is not a number");
+    }
+}
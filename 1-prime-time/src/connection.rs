@@ -1,31 +1,60 @@
+use std::collections::VecDeque;
+use std::io;
+use std::panic;
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::{anyhow, Result};
 use bytes::{Buf, BytesMut};
+use protohackers_core::limits::Rejection;
+use protohackers_core::metrics::{Metered, FRAMES_PARSED};
+use protohackers_core::timeout::{timeout, TimedOut};
 use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinError, JoinHandle};
 
+use crate::deadline::Cancellation;
 use crate::frame::Frame;
-use crate::handler::handle;
+use crate::handler::{self, Error, Registry, REQUESTS_MALFORMED};
 
 struct Connection {
-    stream: BufWriter<TcpStream>,
+    stream: BufWriter<Metered<TcpStream>>,
     buffer: BytesMut,
+    /// Bytes at the start of `buffer` already searched for a newline
+    scanned: usize,
+    /// Close the connection if no bytes arrive for this long
+    idle_timeout: Option<Duration>,
+    /// Longest request line accepted, newline excluded
+    max_frame_size: usize,
 }
 
 impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
+    pub fn new(stream: TcpStream, idle_timeout: Option<Duration>, max_frame_size: usize) -> Self {
         Self {
-            stream: BufWriter::new(stream),
+            stream: BufWriter::new(Metered::new(stream)),
             buffer: BytesMut::new(),
+            scanned: 0,
+            idle_timeout,
+            max_frame_size,
         }
     }
 
+    /// Read the next request line. Fails with [`Error::FrameTooLong`] past `max_frame_size`.
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
         loop {
             if let Some(new_frame) = self.parse_frame() {
+                if new_frame.0.len() > self.max_frame_size {
+                    return Err(Error::FrameTooLong.into());
+                }
                 return Ok(Some(new_frame));
             }
+            if self.buffer.len() > self.max_frame_size {
+                return Err(Error::FrameTooLong.into());
+            }
 
-            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            let read = timeout(self.idle_timeout, self.stream.read_buf(&mut self.buffer)).await?;
+            if read? == 0 {
                 if self.buffer.is_empty() {
                     return Ok(None);
                 } else {
@@ -44,33 +73,153 @@ impl Connection {
     }
 
     fn parse_frame(&mut self) -> Option<Frame> {
-        if let Some((frame, len)) = Frame::parse(&self.buffer) {
+        if let Some((frame, len)) = Frame::parse(&self.buffer, &mut self.scanned) {
             self.buffer.advance(len);
+            FRAMES_PARSED.inc();
             return Some(frame);
         }
         None
     }
 }
 
-pub async fn handle_connection(stream: TcpStream) -> Result<()> {
-    let mut connection = Connection::new(stream);
+/// Most requests of a connection handled at the same time. Past that, the connection is not
+/// read until the oldest one is answered.
+const MAX_IN_FLIGHT: usize = 64;
 
-    while let Some(new_frame) = connection.read_frame().await? {
-        match handle(new_frame.0) {
-            Ok(response) => {
-                let frame = Frame::from(response);
-                connection.write_frame(frame).await?;
+/// Requests of a connection being handled, oldest first.
+///
+/// Those not started yet are dropped with the connection, instead of being handled for a
+/// client that is gone, and the running ones are cancelled.
+#[derive(Default)]
+struct InFlight {
+    tasks: VecDeque<JoinHandle<Result<Vec<u8>, Error>>>,
+    /// Set once the connection is dropped
+    cancellation: Cancellation,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.cancellation.cancel();
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Answer the requests of a connection until it closes.
+///
+/// Requests are handled concurrently on the blocking thread pool, so a slow one doesn't hold
+/// back the ones after it, but the responses are still sent in the order of the requests.
+/// Each one first waits for one of the `permits` shared by every connection.
+pub async fn handle_connection(
+    stream: TcpStream,
+    idle_timeout: Option<Duration>,
+    max_frame_size: usize,
+    registry: Arc<Registry>,
+    permits: Arc<Semaphore>,
+) -> Result<()> {
+    let mut connection = Connection::new(stream, idle_timeout, max_frame_size);
+    let mut in_flight = InFlight::default();
+    let cancellation = in_flight.cancellation.clone();
+    let in_flight = &mut in_flight.tasks;
+    let mut reading = true;
+
+    loop {
+        tokio::select! {
+            // Answer as soon as possible to keep the number of requests in flight low
+            biased;
+            response = oldest_response(in_flight), if !in_flight.is_empty() => {
+                in_flight.pop_front();
+                match response? {
+                    Ok(response) => connection.write_frame(Frame::from(response)).await?,
+                    Err(err) => {
+                        // A malformed request gets an error object back and the connection
+                        // terminated. Requests after it are dropped.
+                        connection.write_frame(Frame::from(err.to_json())).await?;
+                        break;
+                    }
+                }
             }
-            Err(err) => {
-                // An error happened while handling the request.
-                // A malformed response must be sent back and the connection terminated
-                let error_message = format!("{{\"error\":\"{}\"}}", err);
-                let error_frame = Frame::from(error_message.as_bytes());
-                connection.write_frame(error_frame).await?;
-                break;
+            frame = connection.read_frame(), if reading && in_flight.len() < MAX_IN_FLIGHT => {
+                match frame {
+                    Ok(Some(frame)) => {
+                        let registry = Arc::clone(&registry);
+                        let handle = move |cancellation: &Cancellation| {
+                            registry.handle(&frame.0, cancellation)
+                        };
+                        let task =
+                            spawn_limited(Arc::clone(&permits), cancellation.clone(), handle);
+                        in_flight.push_back(task);
+                    }
+                    // Still answer the requests already received
+                    Ok(None) => reading = false,
+                    Err(err) if err.is::<TimedOut>() => {
+                        info!("Closing idle connection");
+                        reading = false;
+                    }
+                    Err(err) if err.is::<Error>() => {
+                        // Answered like a malformed request, after the ones before it
+                        warn!("Request too long. max_frame_size={}", connection.max_frame_size);
+                        REQUESTS_MALFORMED.inc();
+                        in_flight.push_back(task::spawn(async { Err(Error::FrameTooLong) }));
+                        reading = false;
+                    }
+                    Err(err) => return Err(err),
+                }
             }
+            else => break,
         }
     }
 
     Ok(())
 }
+
+/// Run `handle` on the blocking thread pool once one of the `permits` is free, unless
+/// `cancellation` is set by then. The permit is held until `handle` returns, so it must stop
+/// early once cancelled.
+fn spawn_limited<F>(
+    permits: Arc<Semaphore>,
+    cancellation: Cancellation,
+    handle: F,
+) -> JoinHandle<Result<Vec<u8>, Error>>
+where
+    F: FnOnce(&Cancellation) -> Result<Vec<u8>, Error> + Send + 'static,
+{
+    task::spawn(async move {
+        let permit = permits
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed");
+        // Only given back once done, even if the connection is dropped meanwhile
+        let work = task::spawn_blocking(move || {
+            let _permit = permit;
+            if cancellation.is_cancelled() {
+                // Never sent anyway
+                return Err(Error::OutOfTime);
+            }
+            handle(&cancellation)
+        });
+        match work.await {
+            Ok(response) => response,
+            Err(err) => panic::resume_unwind(err.into_panic()),
+        }
+    })
+}
+
+/// Tell a client refused by the connection limits why, with an error object.
+pub async fn reject_connection(stream: TcpStream, rejection: Rejection) -> io::Result<()> {
+    let mut stream = Metered::new(stream);
+    let mut error = handler::error_object(rejection);
+    error.push(b'\n');
+    stream.write_all(&error).await?;
+    stream.shutdown().await
+}
+
+/// Wait for the oldest request in flight, without removing it.
+async fn oldest_response<T>(in_flight: &mut VecDeque<JoinHandle<T>>) -> Result<T, JoinError> {
+    match in_flight.front_mut() {
+        Some(task) => task.await,
+        // Never the case, but the future of a disabled `select!` branch is still created
+        None => std::future::pending().await,
+    }
+}
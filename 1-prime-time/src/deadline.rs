@@ -0,0 +1,41 @@
+//! When the methods with a time budget give up.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Set once the answer to a request is not wanted anymore, like when its connection closed.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Point in time past which a computation gives up, or earlier if it is cancelled.
+#[derive(Clone, Debug)]
+pub struct Deadline {
+    at: Instant,
+    cancellation: Cancellation,
+}
+
+impl Deadline {
+    /// `budget` from now, or as soon as `cancellation` is set.
+    pub fn after(budget: Duration, cancellation: &Cancellation) -> Self {
+        Self {
+            at: Instant::now() + budget,
+            cancellation: cancellation.clone(),
+        }
+    }
+
+    /// Whether the computation must stop.
+    pub fn passed(&self) -> bool {
+        self.cancellation.is_cancelled() || Instant::now() > self.at
+    }
+}
@@ -0,0 +1,270 @@
+//! Integer factorization: trial division for the small factors, then Pollard's rho.
+
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_traits::{One, Zero};
+
+use crate::deadline::Deadline;
+use crate::primality::is_prime;
+
+/// Trial division handles factors below this, Pollard's rho the others
+const TRIAL_DIVISION_BOUND: u32 = 10_000;
+
+/// Steps between two gcd computations in Brent's variant of Pollard's rho
+const BATCH_SIZE: u64 = 128;
+
+/// Prime factors of a number, possibly short of some that took too long to find.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Factorization {
+    /// Prime factors in ascending order, with multiplicity
+    pub primes: Vec<BigUint>,
+    /// Factors not known to be prime when the deadline passed, in ascending order.
+    /// Multiplied with `primes`, they give back the number.
+    pub unfactored: Vec<BigUint>,
+}
+
+impl Factorization {
+    /// Whether every factor is known to be prime.
+    pub fn is_complete(&self) -> bool {
+        self.unfactored.is_empty()
+    }
+}
+
+/// Factorize `number`, giving up on the factors still left at `deadline`.
+///
+/// 0 and 1 have no prime factors.
+pub fn factorize(number: &BigUint, deadline: &Deadline) -> Factorization {
+    let mut factorization = Factorization::default();
+    if number.is_zero() {
+        return factorization;
+    }
+
+    let mut number = number.clone();
+    let mut divisor = 2u32;
+    // Each division takes a while on huge numbers
+    'trial_division: while divisor < TRIAL_DIVISION_BOUND && number > BigUint::one() {
+        while (&number % divisor).is_zero() {
+            if deadline.passed() {
+                break 'trial_division;
+            }
+            factorization.primes.push(divisor.into());
+            number /= divisor;
+        }
+        if deadline.passed() {
+            break;
+        }
+        divisor += if divisor == 2 { 1 } else { 2 };
+    }
+
+    // Split the cofactors until they are all prime
+    let mut cofactors = vec![number];
+    while let Some(cofactor) = cofactors.pop() {
+        if cofactor.is_one() {
+            continue;
+        }
+        if deadline.passed() {
+            factorization.unfactored.push(cofactor);
+            continue;
+        }
+        if is_prime(&BigInt::from(cofactor.clone())) {
+            factorization.primes.push(cofactor);
+            continue;
+        }
+        match find_divisor(&cofactor, deadline) {
+            Some(divisor) => {
+                cofactors.push(&cofactor / &divisor);
+                cofactors.push(divisor);
+            }
+            None => factorization.unfactored.push(cofactor),
+        }
+    }
+
+    factorization.primes.sort();
+    factorization.unfactored.sort();
+    factorization
+}
+
+/// A non-trivial divisor of the odd composite `number`, or `None` past `deadline`.
+fn find_divisor(number: &BigUint, deadline: &Deadline) -> Option<BigUint> {
+    // Each constant gives a different pseudo-random sequence. Keep trying until one of them
+    // finds a divisor other than `number` itself.
+    let mut c = BigUint::one();
+    loop {
+        match brent(number, &c, deadline)? {
+            Some(divisor) => return Some(divisor),
+            None => c += 1u32,
+        }
+    }
+}
+
+/// Brent's variant of Pollard's rho with the sequence x -> x^2 + c.
+///
+/// Returns `None` when out of time and `Some(None)` when this `c` only finds `number` itself.
+fn brent(number: &BigUint, c: &BigUint, deadline: &Deadline) -> Option<Option<BigUint>> {
+    let step = |x: &BigUint| (x * x + c) % number;
+    let distance = |a: &BigUint, b: &BigUint| if a > b { a - b } else { b - a };
+
+    let mut y = BigUint::from(2u32);
+    let mut x = y.clone();
+    let mut saved_y = y.clone();
+    let mut product = BigUint::one();
+    let mut divisor = BigUint::one();
+    let mut length = 1u64;
+    while divisor.is_one() {
+        x = y.clone();
+        for skipped in 0..length {
+            // `length` doubles every round, so the skipped steps alone can take seconds
+            if skipped % BATCH_SIZE == 0 && deadline.passed() {
+                return None;
+            }
+            y = step(&y);
+        }
+        let mut done = 0;
+        while done < length && divisor.is_one() {
+            if deadline.passed() {
+                return None;
+            }
+            saved_y = y.clone();
+            // Multiply the distances together to compute a single gcd per batch
+            for _ in 0..BATCH_SIZE.min(length - done) {
+                y = step(&y);
+                product = product * distance(&x, &y) % number;
+            }
+            divisor = product.gcd(number);
+            done += BATCH_SIZE;
+        }
+        length *= 2;
+    }
+
+    if divisor == *number {
+        // The batch overshot, or hit a multiple of every factor. Replay it one step at a time.
+        loop {
+            saved_y = step(&saved_y);
+            divisor = distance(&x, &saved_y).gcd(number);
+            if !divisor.is_one() {
+                break;
+            }
+        }
+    }
+    Some((divisor != *number).then_some(divisor))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::deadline::Cancellation;
+    use crate::primality::next_prime;
+
+    fn big(number: &str) -> BigUint {
+        BigUint::from_str(number).unwrap()
+    }
+
+    fn factorize_within(number: &BigUint, budget: Duration) -> Factorization {
+        factorize(number, &Deadline::after(budget, &Cancellation::default()))
+    }
+
+    fn primes(number: &BigUint) -> Vec<BigUint> {
+        let factorization = factorize_within(number, Duration::from_secs(10));
+        assert!(factorization.is_complete(), "{number}");
+        factorization.primes
+    }
+
+    #[test]
+    fn test_small_numbers() {
+        assert!(primes(&BigUint::one()).is_empty());
+        for number in 2..2_000u32 {
+            let primes = primes(&number.into());
+            assert_eq!(primes.iter().product::<BigUint>(), number.into());
+            assert!(primes.iter().all(|p| is_prime(&BigInt::from(p.clone()))));
+        }
+    }
+
+    #[test]
+    fn test_pollard_rho() {
+        // 2^64 + 1
+        assert_eq!(
+            primes(&big("18446744073709551617")),
+            [big("274177"), big("67280421310721")]
+        );
+        // 2^67 - 1
+        assert_eq!(
+            primes(&big("147573952589676412927")),
+            [big("193707721"), big("761838257287")]
+        );
+        // Square of a prime above the trial division bound
+        assert_eq!(primes(&big("10000600009")), [big("100003"), big("100003")]);
+        // Mix of small and large factors with multiplicity
+        let number = big("1000003") * big("1000003") * big("999983") * 8u32 * 9u32;
+        assert_eq!(
+            primes(&number),
+            [2u32, 2, 2, 3, 3, 999_983, 1_000_003, 1_000_003].map(BigUint::from)
+        );
+    }
+
+    #[test]
+    fn test_deadline_in_trial_division() {
+        // 1e300000, with too many small factors to divide out in time
+        let number = BigUint::from(10u32).pow(300_000);
+        let started = Instant::now();
+        let factorization = factorize_within(&number, Duration::from_millis(10));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!factorization.is_complete());
+        let product: BigUint = factorization
+            .primes
+            .iter()
+            .chain(&factorization.unfactored)
+            .product();
+        assert_eq!(product, number);
+    }
+
+    #[test]
+    fn test_deadline() {
+        // Product of two 31 digit primes, far out of reach
+        let p = big("1000000000000000000000000000057");
+        let q = next_prime(
+            &p.clone().into(),
+            &Deadline::after(Duration::from_secs(10), &Cancellation::default()),
+        )
+        .unwrap()
+        .to_biguint()
+        .unwrap();
+        let hard = p * q;
+        let number = &hard * 12u32;
+        let factorization = factorize_within(&number, Duration::from_millis(20));
+        assert!(!factorization.is_complete());
+        assert_eq!(factorization.primes, [2u32, 2, 3].map(BigUint::from));
+        assert_eq!(factorization.unfactored, [hard]);
+    }
+
+    #[test]
+    fn test_deadline_on_large_semiprime() {
+        // Product of the Mersenne primes 2^1279 - 1 and 2^2203 - 1, almost 3500 bits
+        let p = (BigUint::one() << 1279u32) - 1u32;
+        let q = (BigUint::one() << 2203u32) - 1u32;
+        let number = &p * &q;
+        // Past trial division, a single primality test runs before the deadline is checked
+        let started = Instant::now();
+        assert!(!is_prime(&BigInt::from(number.clone())));
+        let primality_test = started.elapsed();
+
+        let budget = Duration::from_millis(100);
+        let started = Instant::now();
+        let factorization = factorize_within(&number, budget);
+        assert!(started.elapsed() < budget + 3 * primality_test);
+        assert_eq!(factorization.unfactored, [number]);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        let cancellation = Cancellation::default();
+        let deadline = Deadline::after(Duration::from_secs(10), &cancellation);
+        cancellation.cancel();
+        // 2^67 - 1, quick to factorize otherwise
+        let number = big("147573952589676412927");
+        let factorization = factorize(&number, &deadline);
+        assert_eq!(factorization.unfactored, [number]);
+    }
+}
@@ -4,14 +4,23 @@ use bytes::{Bytes, BytesMut};
 pub struct Frame(pub Bytes);
 
 impl Frame {
-    pub fn parse(buffer: &BytesMut) -> Option<(Self, usize)> {
-        for (position, &byte) in buffer.iter().enumerate() {
-            if byte == b'\n' {
+    /// Parse the first line of `buffer`, returning it with the number of bytes it took.
+    ///
+    /// `scanned` bytes at the start of `buffer` are known not to hold a newline. It is updated
+    /// when there is no full line yet, so that each byte is only looked at once across calls.
+    pub fn parse(buffer: &BytesMut, scanned: &mut usize) -> Option<(Self, usize)> {
+        match buffer[*scanned..].iter().position(|&byte| byte == b'\n') {
+            Some(offset) => {
+                let position = *scanned + offset;
+                *scanned = 0;
                 let frame = Frame(Bytes::copy_from_slice(&buffer[0..position]));
-                return Some((frame, position + 1));
+                Some((frame, position + 1))
+            }
+            None => {
+                *scanned = buffer.len();
+                None
             }
         }
-        None
     }
 }
 
@@ -36,15 +45,27 @@ mod tests {
     #[test]
     fn test_parse_frame() {
         let mut buffer = BytesMut::new();
+        let mut scanned = 0;
 
         buffer.put(&b"{\"method\":\"isPrime\","[..]);
-        assert!(Frame::parse(&buffer).is_none());
+        assert!(Frame::parse(&buffer, &mut scanned).is_none());
+        assert_eq!(scanned, buffer.len());
 
-        buffer.put(&b"\"number\":1}\n"[..]);
-        let (frame, _) = Frame::parse(&buffer).unwrap();
+        buffer.put(&b"\"number\":1}\n{"[..]);
+        let (frame, length) = Frame::parse(&buffer, &mut scanned).unwrap();
         assert_eq!(
             frame,
             Frame::from(&b"{\"method\":\"isPrime\",\"number\":1}"[..])
         );
+        assert_eq!(length, buffer.len() - 1);
+        assert_eq!(scanned, 0);
+    }
+
+    #[test]
+    fn test_parse_empty_frames() {
+        let buffer = BytesMut::from(&b"\n\n"[..]);
+        let mut scanned = 0;
+        let (frame, length) = Frame::parse(&buffer, &mut scanned).unwrap();
+        assert_eq!((frame, length), (Frame::from(&b""[..]), 1));
     }
 }
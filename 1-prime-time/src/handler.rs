@@ -1,98 +1,240 @@
-use std::str::FromStr;
-
-use anyhow::{bail, Result};
-use bytes::Bytes;
-use num_bigint::BigInt;
-use num_traits::cast::ToPrimitive;
-use serde::{Deserialize, Serialize};
-use serde_json::Number;
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Request {
-    method: String,
-    number: Number,
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use protohackers_core::metrics::{self, Counter, PARSE_ERRORS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::cache::{CACHE_HITS, CACHE_MISSES};
+use crate::deadline::Cancellation;
+use crate::methods::{
+    Factorize, Gcd, IsPrime, IsProbablePrime, NextPrime, PrimeCount, DEFAULT_NEXT_PRIME_BUDGET,
+};
+use crate::server::Options;
+use crate::sieve::Sieve;
+
+const REQUESTS_HELP: &str = "Requests answered, by result";
+pub(crate) static REQUESTS_PRIME: Counter = Counter::with_labels(
+    "prime_time_requests_total",
+    REQUESTS_HELP,
+    &[("result", "prime")],
+);
+pub(crate) static REQUESTS_NOT_PRIME: Counter = Counter::with_labels(
+    "prime_time_requests_total",
+    REQUESTS_HELP,
+    &[("result", "not_prime")],
+);
+pub(crate) static FACTORIZATIONS_INCOMPLETE: Counter = Counter::new(
+    "prime_time_factorizations_incomplete_total",
+    "Factorizations cut short by their time budget",
+);
+pub(crate) static REQUESTS_MALFORMED: Counter = Counter::with_labels(
+    "prime_time_requests_total",
+    REQUESTS_HELP,
+    &[("result", "malformed")],
+);
+
+pub(crate) fn register_metrics() {
+    metrics::register(&[
+        &REQUESTS_PRIME,
+        &REQUESTS_NOT_PRIME,
+        &REQUESTS_MALFORMED,
+        &FACTORIZATIONS_INCOMPLETE,
+        &CACHE_HITS,
+        &CACHE_MISSES,
+    ]);
 }
 
-#[derive(Debug, Serialize)]
-struct Response {
-    method: String,
-    prime: bool,
+/// Why a request is malformed. The client gets it back as `{"error":"..."}`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Not a JSON object with a string `method`
+    InvalidJson,
+    /// No method registered with that name
+    UnknownMethod,
+    /// The fields of the request don't fit the method
+    InvalidParams(String),
+    /// The request has a field the method doesn't take, in strict mode
+    UnknownField(String),
+    /// The request line is longer than the server accepts
+    FrameTooLong,
+    /// The method ran out of time before it had an answer
+    OutOfTime,
 }
 
-pub fn handle(request: Bytes) -> Result<Vec<u8>> {
-    debug!("Received request: {}", String::from_utf8_lossy(&request));
-    let request = match serde_json::from_slice::<Request>(&request.to_vec()) {
-        Err(error) => {
-            warn!(
-                "Could not decode json. error={}, request={}",
-                error,
-                String::from_utf8_lossy(&request[..])
-            );
-            bail!("invalid json");
+impl Error {
+    /// The error object sent to the client.
+    pub fn to_json(&self) -> Vec<u8> {
+        error_object(self)
+    }
+}
+
+/// `{"error":"..."}` with the `reason` an error response or a refused connection gets.
+pub fn error_object(reason: impl Display) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({ "error": reason.to_string() }))
+        .expect("error objects are valid JSON")
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidJson => f.write_str("invalid json"),
+            Error::UnknownMethod => f.write_str("invalid method"),
+            Error::InvalidParams(reason) => write!(f, "invalid params: {reason}"),
+            Error::UnknownField(field) => write!(f, "unknown field `{field}`"),
+            Error::FrameTooLong => f.write_str("request too long"),
+            Error::OutOfTime => f.write_str("out of time"),
         }
-        Ok(request) => request,
-    };
-
-    if request.method != "isPrime" {
-        bail!("invalid method");
-    }
-
-    let is_prime = match BigInt::from_str(&request.number.to_string()) {
-        Ok(bigint) => match bigint.to_u64() {
-            // Abuse the fact that every BigInt sent is not prime
-            None => false,
-            Some(n) => is_prime(n),
-        },
-        // Failed to parse BigInt. It's a float, which is not prime.
-        Err(_) => false,
-    };
-
-    let response = Response {
-        method: "isPrime".to_string(),
-        prime: is_prime,
-    };
-    debug!("Sending response: {response:?}");
-
-    Ok(serde_json::to_vec(&response)?)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A method clients can call, with the fields it expects and answers with.
+///
+/// Both are flat in the request and response objects, next to `method`.
+pub trait Method: Send + Sync + 'static {
+    type Request: DeserializeOwned;
+    type Response: Serialize;
+
+    /// Answer `request`. Long computations stop early once `cancellation` is set, as nobody
+    /// waits for their answer anymore.
+    fn call(
+        &self,
+        request: Self::Request,
+        cancellation: &Cancellation,
+    ) -> Result<Self::Response, Error>;
+}
+
+/// Answers a request, given whether unknown fields are rejected.
+type Handler =
+    Box<dyn Fn(Map<String, Value>, bool, &Cancellation) -> Result<Vec<u8>, Error> + Send + Sync>;
+
+/// Methods by name.
+#[derive(Default)]
+pub struct Registry {
+    methods: HashMap<&'static str, Handler>,
+    strict: bool,
+}
+
+#[derive(Serialize)]
+struct Response<'a, R> {
+    method: &'a str,
+    #[serde(flatten)]
+    result: R,
 }
 
-fn is_prime(number: u64) -> bool {
-    if number <= 1 {
-        return false;
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
     }
-    if number == 2 {
-        return true;
+
+    /// Every method this server knows about, set up with `options`.
+    pub fn with_default_methods(options: &Options) -> Self {
+        let mut registry = Self {
+            strict: options.strict,
+            ..Self::new()
+        };
+        let sieve = Arc::new(Sieve::new(options.sieve_bound));
+        registry.register(
+            "isPrime",
+            IsPrime::new(Arc::clone(&sieve), options.cache_size),
+        );
+        registry.register(
+            "nextPrime",
+            NextPrime::new(Arc::clone(&sieve), DEFAULT_NEXT_PRIME_BUDGET),
+        );
+        registry.register("primeCount", PrimeCount::new(sieve));
+        registry.register("factorize", Factorize::default());
+        registry.register("gcd", Gcd);
+        registry.register("isProbablePrime", IsProbablePrime);
+        registry
     }
-    if number % 2 == 0 {
-        return false;
+
+    /// Answer requests for `name` with `method`, replacing any method of the same name.
+    pub fn register<M: Method>(&mut self, name: &'static str, method: M) {
+        let handler =
+            move |request: Map<String, Value>, strict: bool, cancellation: &Cancellation| {
+                let mut unknown = Vec::new();
+                let request = serde_ignored::deserialize(Value::Object(request), |path| {
+                    unknown.push(path.to_string())
+                })
+                .map_err(|err| Error::InvalidParams(err.to_string()))?;
+                if strict {
+                    if let Some(field) = unknown.into_iter().find(|field| field != "method") {
+                        return Err(Error::UnknownField(field));
+                    }
+                }
+                let response = Response {
+                    method: name,
+                    result: method.call(request, cancellation)?,
+                };
+                debug!(
+                    "Sending response: {}",
+                    serde_json::to_string(&response).unwrap()
+                );
+                Ok(serde_json::to_vec(&response).expect("responses are valid JSON"))
+            };
+        self.methods.insert(name, Box::new(handler));
     }
 
-    let limit = f64::sqrt(number as f64).trunc() as u64;
-    for n in (3..limit).step_by(2) {
-        if number % n == 0 {
-            return false;
+    /// Answer a single request line, unless `cancellation` is set first.
+    pub fn handle(&self, request: &[u8], cancellation: &Cancellation) -> Result<Vec<u8>, Error> {
+        debug!("Received request: {}", String::from_utf8_lossy(request));
+        let result = self.dispatch(request, cancellation);
+        if let Err(err) = &result {
+            warn!(
+                "Malformed request: {err}. request={}",
+                String::from_utf8_lossy(request)
+            );
+            if *err == Error::InvalidJson {
+                PARSE_ERRORS.inc();
+            }
+            REQUESTS_MALFORMED.inc();
         }
+        result
     }
 
-    true
+    fn dispatch(&self, request: &[u8], cancellation: &Cancellation) -> Result<Vec<u8>, Error> {
+        let Ok(Value::Object(request)) = serde_json::from_slice(request) else {
+            return Err(Error::InvalidJson);
+        };
+        let Some(Value::String(method)) = request.get("method") else {
+            return Err(Error::InvalidJson);
+        };
+        let handler = self
+            .methods
+            .get(method.as_str())
+            .ok_or(Error::UnknownMethod)?;
+        handler(request, self.strict, cancellation)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::OnceLock;
+
+    use bytes::Bytes;
+
     use super::*;
 
-    #[test]
-    fn test_deserialize() {
-        serde_json::from_str::<Request>(&r#"{"number":-3,"method":"isPrime"}"#).unwrap();
+    fn handle(request: Bytes) -> Result<Vec<u8>, Error> {
+        static REGISTRY: OnceLock<Registry> = OnceLock::new();
+        REGISTRY
+            .get_or_init(|| Registry::with_default_methods(&Options::default()))
+            .handle(&request, &Cancellation::default())
+    }
+
+    fn handle_str(request: &str) -> Result<String, Error> {
+        handle(Bytes::from(request.to_string()))
+            .map(|response| String::from_utf8(response).unwrap())
     }
 
     #[test]
-    fn test_is_prime() {
-        assert!(!is_prime(1));
-        assert!(is_prime(2));
-        assert!(is_prime(3));
-        assert!(!is_prime(21));
-        assert!(is_prime(23));
+    fn test_deserialize() {
+        handle_str(r#"{"number":-3,"method":"isPrime"}"#).unwrap();
     }
 
     #[test]
@@ -118,4 +260,270 @@ mod tests {
             String::from_utf8_lossy(&response),
         );
     }
+
+    #[test]
+    fn test_handler_big_prime() {
+        // 2^127 - 1
+        let request_str =
+            r#"{"method":"isPrime","number":170141183460469231731687303715884105727}"#;
+        let response = handle(Bytes::from(request_str)).unwrap();
+        assert_eq!(
+            r#"{"method":"isPrime","prime":true}"#,
+            String::from_utf8_lossy(&response),
+        );
+    }
+
+    #[test]
+    fn test_oversized_operands() {
+        // Odd, so that it would take a full primality test
+        let too_big = (num_bigint::BigInt::from(1) << 4096u32) + 1;
+        let request = format!(r#"{{"method":"isPrime","number":{too_big}}}"#);
+        assert!(matches!(handle_str(&request), Err(Error::InvalidParams(_))));
+
+        let request = format!(r#"{{"method":"isProbablePrime","number":{too_big},"rounds":1}}"#);
+        assert!(matches!(handle_str(&request), Err(Error::InvalidParams(_))));
+        let request = format!(r#"{{"method":"factorize","number":-{too_big}}}"#);
+        assert!(matches!(handle_str(&request), Err(Error::InvalidParams(_))));
+
+        // Past the digit limit, refused by every method
+        let too_long = "7".repeat(10_001);
+        for request in [
+            format!(r#"{{"method":"isPrime","number":{too_long}}}"#),
+            format!(r#"{{"method":"isPrime","number":-{too_long}}}"#),
+            format!(r#"{{"method":"gcd","numbers":[{too_long}]}}"#),
+        ] {
+            assert!(
+                matches!(handle_str(&request), Err(Error::InvalidParams(_))),
+                "{request:.40}"
+            );
+        }
+
+        // Never prime, whatever their size
+        let request = format!(r#"{{"method":"isPrime","number":-{too_big}}}"#);
+        assert_eq!(
+            handle_str(&request).unwrap(),
+            r#"{"method":"isPrime","prime":false}"#
+        );
+    }
+
+    #[test]
+    fn test_methods() {
+        for (request, response) in [
+            (
+                r#"{"method":"nextPrime","number":24}"#,
+                r#"{"method":"nextPrime","number":29}"#,
+            ),
+            (
+                r#"{"method":"factorize","number":-360}"#,
+                r#"{"method":"factorize","factors":[-1,2,2,2,3,3,5],"complete":true}"#,
+            ),
+            (
+                r#"{"method":"nextPrime","number":9999991}"#,
+                r#"{"method":"nextPrime","number":10000019}"#,
+            ),
+            (
+                r#"{"method":"nextPrime","number":18446744073709551557}"#,
+                r#"{"method":"nextPrime","number":18446744073709551629}"#,
+            ),
+            (
+                r#"{"method":"primeCount","number":1000000}"#,
+                r#"{"method":"primeCount","count":78498}"#,
+            ),
+            (
+                r#"{"method":"primeCount","number":-5}"#,
+                r#"{"method":"primeCount","count":0}"#,
+            ),
+            (
+                r#"{"method":"gcd","numbers":[12,-18,30]}"#,
+                r#"{"method":"gcd","gcd":6}"#,
+            ),
+            (
+                r#"{"method":"isProbablePrime","number":170141183460469231731687303715884105727,"rounds":3}"#,
+                r#"{"method":"isProbablePrime","prime":true}"#,
+            ),
+            (
+                r#"{"method":"isProbablePrime","number":561}"#,
+                r#"{"method":"isProbablePrime","prime":false}"#,
+            ),
+        ] {
+            assert_eq!(handle_str(request).unwrap(), response, "{request}");
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        for (request, error) in [
+            ("{", Error::InvalidJson),
+            ("[1,2]", Error::InvalidJson),
+            (r#"{"number":2}"#, Error::InvalidJson),
+            (r#"{"method":2,"number":2}"#, Error::InvalidJson),
+            (r#"{"method":"isprime","number":2}"#, Error::UnknownMethod),
+        ] {
+            assert_eq!(handle_str(request), Err(error), "{request}");
+        }
+        for request in [
+            r#"{"method":"isPrime"}"#,
+            r#"{"method":"isPrime","number":"2"}"#,
+            r#"{"method":"nextPrime","number":2.5}"#,
+            r#"{"method":"nextPrime","number":1e1300}"#,
+            r#"{"method":"gcd","numbers":[]}"#,
+            r#"{"method":"primeCount","number":10000000}"#,
+            r#"{"method":"factorize","number":0}"#,
+            r#"{"method":"isProbablePrime","number":7,"rounds":0}"#,
+        ] {
+            assert!(
+                matches!(handle_str(request), Err(Error::InvalidParams(_))),
+                "{request}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_json() {
+        assert_eq!(
+            Error::UnknownMethod.to_json(),
+            br#"{"error":"invalid method"}"#
+        );
+        assert_eq!(
+            Error::InvalidParams("\"quoted\"".to_string()).to_json(),
+            br#"{"error":"invalid params: \"quoted\""}"#
+        );
+    }
+
+    #[test]
+    fn test_number_notations() {
+        for (request, response) in [
+            (
+                r#"{"method":"isPrime","number":7.0}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":7e0}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":0.7e1}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":7.5}"#,
+                r#"{"method":"isPrime","prime":false}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":-0}"#,
+                r#"{"method":"isPrime","prime":false}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":1e3}"#,
+                r#"{"method":"isPrime","prime":false}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":1e999999999}"#,
+                r#"{"method":"isPrime","prime":false}"#,
+            ),
+            (
+                r#"{"method":"nextPrime","number":1e2}"#,
+                r#"{"method":"nextPrime","number":101}"#,
+            ),
+            (
+                r#"{"method":"gcd","numbers":[1.2e1,18.0]}"#,
+                r#"{"method":"gcd","gcd":6}"#,
+            ),
+        ] {
+            assert_eq!(handle_str(request).unwrap(), response, "{request}");
+        }
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let lenient = Registry::with_default_methods(&Options::default());
+        let strict = Registry::with_default_methods(&Options {
+            strict: true,
+            ..Options::default()
+        });
+        let message = |registry: &Registry, request: &str| match registry
+            .handle(request.as_bytes(), &Cancellation::default())
+        {
+            Ok(response) => String::from_utf8(response).unwrap(),
+            Err(err) => err.to_string(),
+        };
+
+        // Request, then the answer in lenient and strict mode
+        for (request, lenient_answer, strict_answer) in [
+            (
+                r#"{"method":"isPrime","number":2}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":2,"bignumber":true}"#,
+                r#"{"method":"isPrime","prime":true}"#,
+                "unknown field `bignumber`",
+            ),
+            (
+                r#"{"method":"gcd","numbers":[4,6],"number":4}"#,
+                r#"{"method":"gcd","gcd":2}"#,
+                "unknown field `number`",
+            ),
+            (
+                r#"{"method":"isPrime","number":"2"}"#,
+                r#"invalid params: invalid type: string "2", expected a JSON number"#,
+                r#"invalid params: invalid type: string "2", expected a JSON number"#,
+            ),
+            (
+                r#"{"method":"isPrime","number":null}"#,
+                "invalid params: invalid type: null, expected a JSON number",
+                "invalid params: invalid type: null, expected a JSON number",
+            ),
+            (
+                r#"{"method":"isPrime"}"#,
+                "invalid params: missing field `number`",
+                "invalid params: missing field `number`",
+            ),
+            (
+                r#"{"method":"nextPrime","number":2.5}"#,
+                "invalid params: expected an integer, got 2.5",
+                "invalid params: expected an integer, got 2.5",
+            ),
+            (
+                r#"{"method":"nextPrime","number":1e999999}"#,
+                "invalid params: integer 1e+999999 has an exponent over 4096",
+                "invalid params: integer 1e+999999 has an exponent over 4096",
+            ),
+            (
+                r#"{"method":"gcd","numbers":4}"#,
+                "invalid params: invalid type: number, expected a sequence",
+                "invalid params: invalid type: number, expected a sequence",
+            ),
+            (
+                r#"{"method":"isProbablePrime","number":7,"rounds":-1}"#,
+                "invalid params: rounds must be between 1 and 25",
+                "invalid params: rounds must be between 1 and 25",
+            ),
+        ] {
+            assert_eq!(message(&lenient, request), lenient_answer, "{request}");
+            assert_eq!(message(&strict, request), strict_answer, "{request}");
+        }
+    }
+
+    #[test]
+    fn test_cache_canonical_numbers() {
+        let registry = Registry::with_default_methods(&Options::default());
+        let hits = CACHE_HITS.get();
+        // 2^127 - 1, written two ways
+        for number in [
+            "170141183460469231731687303715884105727",
+            "1.70141183460469231731687303715884105727e38",
+        ] {
+            let request = format!(r#"{{"method":"isPrime","number":{number}}}"#);
+            assert_eq!(
+                registry
+                    .handle(request.as_bytes(), &Cancellation::default())
+                    .unwrap(),
+                br#"{"method":"isPrime","prime":true}"#
+            );
+        }
+        // Other tests may hit the cache of their own registry at the same time
+        assert!(CACHE_HITS.get() > hits);
+    }
 }
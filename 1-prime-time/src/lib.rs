@@ -1,7 +1,15 @@
 #[macro_use]
 extern crate log;
 
+mod cache;
+pub mod client;
 mod connection;
+mod deadline;
+mod factorization;
 mod frame;
 mod handler;
+mod methods;
+mod number;
+mod primality;
 pub mod server;
+mod sieve;
@@ -1,21 +1,70 @@
-extern crate core;
 #[macro_use]
 extern crate log;
 
+use std::path::PathBuf;
+
 use anyhow::Result;
-use pretty_env_logger::env_logger::Env;
+use clap::Parser;
 use tokio::net::TcpListener;
 
 use prime_time::server;
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::server::Server;
+use protohackers_core::{cli, logging, metrics};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(flatten)]
+    server: ServerArgs,
+
+    /// Longest request line accepted, in bytes. Longer ones are answered with an error
+    #[arg(long, default_value_t = server::DEFAULT_MAX_FRAME_SIZE)]
+    max_frame_size: usize,
+
+    /// Reject requests with fields their method doesn't take, instead of ignoring them
+    #[arg(long)]
+    strict: bool,
+
+    /// How many primality results for numbers between 2^64 and 2^4096 to remember. 0 disables
+    /// the cache
+    #[arg(long, default_value_t = server::DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
+
+    /// Sieve the numbers below this at startup to answer them right away. 0 disables the sieve
+    #[arg(long, default_value_t = server::DEFAULT_SIEVE_BOUND)]
+    sieve_bound: u64,
+
+    /// Requests handled at the same time across every connection. The others wait their turn
+    #[arg(long, default_value_t = server::DEFAULT_MAX_CONCURRENT_REQUESTS)]
+    max_concurrent_requests: usize,
+
+    /// TOML file with default values for these options, like `max_frame_size = 4096`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    pretty_env_logger::env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .init();
+    let mut cli: Cli = cli::parse(9901);
+    if let Some(config) = &cli.config {
+        cli = cli::parse_with_config_file(9901, config)?;
+    }
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
 
-    let bind_address = "0.0.0.0:9901";
-    let listener = TcpListener::bind(bind_address).await?;
-    info!("Starting server at {bind_address}");
+    let listener = TcpListener::bind(cli.server.bind_address()).await?;
+    info!("Starting server at {}", listener.local_addr()?);
 
-    server::run(listener).await
+    let server = Server::new(listener).with_config(cli.server.server_config());
+    server.shutdown_handle().trigger_on_signal();
+    let options = server::Options {
+        max_frame_size: cli.max_frame_size,
+        strict: cli.strict,
+        cache_size: cli.cache_size,
+        sieve_bound: cli.sieve_bound,
+        max_concurrent_requests: cli.max_concurrent_requests,
+    };
+    server::serve(server, options).await
 }
@@ -0,0 +1,418 @@
+//! The methods answered by the server, each with its own request and response.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer as _;
+use num_traits::{Signed, ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+
+use crate::cache::PrimalityCache;
+use crate::deadline::{Cancellation, Deadline};
+use crate::factorization::factorize;
+use crate::handler::{
+    Error, Method, FACTORIZATIONS_INCOMPLETE, REQUESTS_NOT_PRIME, REQUESTS_PRIME,
+};
+use crate::number::{classify, too_long, Integer, Kind};
+use crate::primality::{self, is_prime, is_prime_u64, is_probable_prime_with_rounds, MAX_ROUNDS};
+use crate::sieve::Sieve;
+
+/// How long `factorize` may look for factors before answering with the ones it found
+pub const DEFAULT_FACTORIZE_BUDGET: Duration = Duration::from_secs(1);
+
+/// How long `nextPrime` may look for a prime before answering with an error
+pub const DEFAULT_NEXT_PRIME_BUDGET: Duration = Duration::from_secs(1);
+
+/// Bits of the largest integer `isPrime`, `isProbablePrime`, `nextPrime` and `factorize` accept,
+/// in absolute value. A single primality test of a bigger one could take seconds, longer than
+/// the budgets of `nextPrime` and `factorize`.
+pub const MAX_OPERAND_BITS: u64 = 4096;
+
+/// Refuse integers too big to compute with in time.
+fn check_operand_size(number: &BigInt) -> Result<(), Error> {
+    if number.bits() > MAX_OPERAND_BITS {
+        return Err(Error::InvalidParams(format!(
+            "number must be below 2^{MAX_OPERAND_BITS}"
+        )));
+    }
+    Ok(())
+}
+
+/// `isPrime`: whether `number` is prime. Numbers with a fractional part are not.
+///
+/// Integers must be below 2^4096. Numbers below the bound of the sieve are looked up in it.
+/// Results for numbers above 2^64 are cached, as they take much longer to compute.
+pub struct IsPrime {
+    sieve: Arc<Sieve>,
+    cache: PrimalityCache,
+}
+
+impl IsPrime {
+    pub fn new(sieve: Arc<Sieve>, cache_size: usize) -> Self {
+        Self {
+            sieve,
+            cache: PrimalityCache::new(cache_size),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IsPrimeRequest {
+    number: Number,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IsPrimeResponse {
+    prime: bool,
+}
+
+impl Method for IsPrime {
+    type Request = IsPrimeRequest;
+    type Response = IsPrimeResponse;
+
+    fn call(&self, request: IsPrimeRequest, _: &Cancellation) -> Result<IsPrimeResponse, Error> {
+        let prime = match classify(&request.number) {
+            Kind::Integer(integer) => match integer.to_u64() {
+                Some(number) => self
+                    .sieve
+                    .is_prime(number)
+                    .unwrap_or_else(|| is_prime_u64(number)),
+                None if integer.is_negative() => false,
+                None => {
+                    check_operand_size(&integer)?;
+                    self.cache.get_or_compute(&integer, is_prime)
+                }
+            },
+            // Multiples of 10
+            Kind::Fraction | Kind::Huge => false,
+            Kind::TooLong => return Err(Error::InvalidParams(too_long())),
+        };
+        if prime {
+            REQUESTS_PRIME.inc();
+        } else {
+            REQUESTS_NOT_PRIME.inc();
+        }
+        Ok(IsPrimeResponse { prime })
+    }
+}
+
+/// `nextPrime`: smallest prime greater than `number`, which must be below 2^4096.
+///
+/// When the time budget runs out before a prime is found, the request fails.
+pub struct NextPrime {
+    sieve: Arc<Sieve>,
+    budget: Duration,
+}
+
+impl NextPrime {
+    pub fn new(sieve: Arc<Sieve>, budget: Duration) -> Self {
+        Self { sieve, budget }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NextPrimeRequest {
+    number: Integer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NextPrimeResponse {
+    number: Integer,
+}
+
+impl Method for NextPrime {
+    type Request = NextPrimeRequest;
+    type Response = NextPrimeResponse;
+
+    fn call(
+        &self,
+        request: NextPrimeRequest,
+        cancellation: &Cancellation,
+    ) -> Result<NextPrimeResponse, Error> {
+        let number = &request.number.0;
+        check_operand_size(number)?;
+        let next_prime = match number.to_u64().and_then(|n| self.sieve.next_prime(n)) {
+            Some(next_prime) => BigInt::from(next_prime),
+            None => primality::next_prime(number, &Deadline::after(self.budget, cancellation))
+                .ok_or(Error::OutOfTime)?,
+        };
+        Ok(NextPrimeResponse {
+            number: next_prime.into(),
+        })
+    }
+}
+
+/// `primeCount`: how many primes are less than or equal to `number`.
+///
+/// Only answered below the bound of the sieve.
+pub struct PrimeCount {
+    sieve: Arc<Sieve>,
+}
+
+impl PrimeCount {
+    pub fn new(sieve: Arc<Sieve>) -> Self {
+        Self { sieve }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PrimeCountRequest {
+    number: Integer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PrimeCountResponse {
+    count: u64,
+}
+
+impl Method for PrimeCount {
+    type Request = PrimeCountRequest;
+    type Response = PrimeCountResponse;
+
+    fn call(
+        &self,
+        request: PrimeCountRequest,
+        _: &Cancellation,
+    ) -> Result<PrimeCountResponse, Error> {
+        let number = &request.number.0;
+        let count = if number.is_negative() {
+            Some(0)
+        } else {
+            number.to_u64().and_then(|n| self.sieve.prime_count(n))
+        };
+        let count = count.ok_or_else(|| {
+            Error::InvalidParams(format!("number must be below {}", self.sieve.bound()))
+        })?;
+        Ok(PrimeCountResponse { count })
+    }
+}
+
+/// `factorize`: prime factors of `number` in ascending order, with multiplicity.
+///
+/// `number` must be below 2^4096 in absolute value. Negative numbers get a leading -1.
+/// When the time budget runs out, `complete` is false
+/// and the factors not known to be prime yet are listed in `unfactored`.
+pub struct Factorize {
+    budget: Duration,
+}
+
+impl Factorize {
+    pub fn new(budget: Duration) -> Self {
+        Self { budget }
+    }
+}
+
+impl Default for Factorize {
+    fn default() -> Self {
+        Self::new(DEFAULT_FACTORIZE_BUDGET)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FactorizeRequest {
+    number: Integer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FactorizeResponse {
+    factors: Vec<Integer>,
+    complete: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unfactored: Vec<Integer>,
+}
+
+impl Method for Factorize {
+    type Request = FactorizeRequest;
+    type Response = FactorizeResponse;
+
+    fn call(
+        &self,
+        request: FactorizeRequest,
+        cancellation: &Cancellation,
+    ) -> Result<FactorizeResponse, Error> {
+        let number = request.number.0;
+        if number.is_zero() {
+            return Err(Error::InvalidParams("cannot factorize 0".to_string()));
+        }
+        check_operand_size(&number)?;
+        let factorization = factorize(
+            number.magnitude(),
+            &Deadline::after(self.budget, cancellation),
+        );
+        if !factorization.is_complete() {
+            FACTORIZATIONS_INCOMPLETE.inc();
+        }
+
+        let integers = |numbers: Vec<BigUint>| numbers.into_iter().map(|n| Integer(n.into()));
+        let sign = number.is_negative().then(|| Integer(BigInt::from(-1)));
+        Ok(FactorizeResponse {
+            complete: factorization.is_complete(),
+            factors: sign
+                .into_iter()
+                .chain(integers(factorization.primes))
+                .collect(),
+            unfactored: integers(factorization.unfactored).collect(),
+        })
+    }
+}
+
+/// `gcd`: greatest common divisor of `numbers`, which is never negative.
+pub struct Gcd;
+
+#[derive(Debug, Deserialize)]
+pub struct GcdRequest {
+    numbers: Vec<Integer>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GcdResponse {
+    gcd: Integer,
+}
+
+impl Method for Gcd {
+    type Request = GcdRequest;
+    type Response = GcdResponse;
+
+    fn call(&self, request: GcdRequest, _: &Cancellation) -> Result<GcdResponse, Error> {
+        if request.numbers.is_empty() {
+            return Err(Error::InvalidParams("numbers is empty".to_string()));
+        }
+        let gcd = request
+            .numbers
+            .iter()
+            .fold(BigInt::zero(), |gcd, number| gcd.gcd(&number.0));
+        Ok(GcdResponse { gcd: gcd.into() })
+    }
+}
+
+/// `isProbablePrime`: Miller–Rabin with `rounds` witnesses, 10 by default.
+///
+/// Cheaper than `isPrime`, but a `true` answer is only probable. `number` must be below 2^4096
+/// too, as each round costs about as much as a Miller–Rabin test of `isPrime`.
+pub struct IsProbablePrime;
+
+#[derive(Debug, Deserialize)]
+pub struct IsProbablePrimeRequest {
+    number: Integer,
+    rounds: Option<Integer>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IsProbablePrimeResponse {
+    prime: bool,
+}
+
+impl Method for IsProbablePrime {
+    type Request = IsProbablePrimeRequest;
+    type Response = IsProbablePrimeResponse;
+
+    fn call(
+        &self,
+        request: IsProbablePrimeRequest,
+        _: &Cancellation,
+    ) -> Result<IsProbablePrimeResponse, Error> {
+        let rounds = match request.rounds {
+            None => 10,
+            Some(rounds) => rounds
+                .0
+                .to_usize()
+                .filter(|rounds| (1..=MAX_ROUNDS).contains(rounds))
+                .ok_or_else(|| {
+                    Error::InvalidParams(format!("rounds must be between 1 and {MAX_ROUNDS}"))
+                })?,
+        };
+        let number = &request.number.0;
+        // Negative numbers are never prime, whatever their size
+        if number.is_positive() {
+            check_operand_size(number)?;
+        }
+        Ok(IsProbablePrimeResponse {
+            prime: is_probable_prime_with_rounds(number, rounds),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use num_traits::Pow;
+
+    use super::*;
+
+    fn factorize(number: &str, budget: Duration) -> Result<FactorizeResponse, Error> {
+        let request = FactorizeRequest {
+            number: Integer(BigInt::from_str(number).unwrap()),
+        };
+        Factorize::new(budget).call(request, &Cancellation::default())
+    }
+
+    fn strings(integers: &[Integer]) -> Vec<String> {
+        integers.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_factorize() {
+        let budget = Duration::from_secs(10);
+        for (number, factors) in [
+            ("1", &[][..]),
+            ("2", &["2"]),
+            ("360", &["2", "2", "2", "3", "3", "5"]),
+            ("-21", &["-1", "3", "7"]),
+            ("999985999949", &["999983", "1000003"]),
+            ("18446744073709551617", &["274177", "67280421310721"]),
+        ] {
+            let response = factorize(number, budget).unwrap();
+            assert!(response.complete, "{number}");
+            assert_eq!(strings(&response.factors), factors, "{number}");
+        }
+        assert!(factorize("0", budget).is_err());
+    }
+
+    #[test]
+    fn test_factorize_budget() {
+        // Product of two 30 digit primes
+        let hard = "1000000000000000000000000000156000000000000000000000000005643";
+        let response = factorize(&format!("-{hard}0"), Duration::from_millis(10)).unwrap();
+        assert!(!response.complete);
+        assert_eq!(strings(&response.factors), ["-1", "2", "5"]);
+        assert_eq!(strings(&response.unfactored), [hard]);
+    }
+
+    #[test]
+    fn test_next_prime_limits() {
+        let next_prime = |number: BigInt, budget: Duration| {
+            let sieve = Arc::new(Sieve::new(0));
+            let request = NextPrimeRequest {
+                number: Integer(number),
+            };
+            NextPrime::new(sieve, budget).call(request, &Cancellation::default())
+        };
+        let huge = BigInt::from(10).pow(1000u32);
+        assert_eq!(
+            next_prime(huge, Duration::ZERO).unwrap_err(),
+            Error::OutOfTime
+        );
+        let too_big = BigInt::from(1) << MAX_OPERAND_BITS;
+        assert!(matches!(
+            next_prime(too_big, Duration::from_secs(10)),
+            Err(Error::InvalidParams(_))
+        ));
+    }
+
+    #[test]
+    fn test_gcd() {
+        let gcd = |numbers: &[i64]| {
+            let numbers = numbers.iter().map(|&n| Integer(BigInt::from(n))).collect();
+            Gcd.call(GcdRequest { numbers }, &Cancellation::default())
+                .map(|response| response.gcd.0)
+        };
+        assert_eq!(gcd(&[12, 18]).unwrap(), BigInt::from(6));
+        assert_eq!(gcd(&[-12, 18, 8]).unwrap(), BigInt::from(2));
+        assert_eq!(gcd(&[0, -5]).unwrap(), BigInt::from(5));
+        assert_eq!(gcd(&[7]).unwrap(), BigInt::from(7));
+        assert!(gcd(&[]).is_err());
+    }
+}
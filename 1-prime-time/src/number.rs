@@ -0,0 +1,236 @@
+//! Exact handling of JSON numbers, which may be integers written like `5.0`, `1e3` or `-0`.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use num_bigint::BigInt;
+use num_traits::Pow;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Number;
+
+/// Largest exponent an integer is computed with, counted from the decimal point.
+///
+/// A few bytes like `1e5000` would otherwise stand for a number of any size.
+pub const MAX_EXPONENT: i128 = 4096;
+
+/// Most digits of an integer computed with, whatever its notation.
+///
+/// A request line of a megabyte holds integers of up to a million digits, which take seconds
+/// to parse or compute with.
+pub const MAX_DIGITS: i128 = 10_000;
+
+/// The exact value of a JSON number.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Any number without a fractional part, whatever its notation
+    Integer(BigInt),
+    /// A number with a fractional part
+    Fraction,
+    /// An integer with an exponent over [`MAX_EXPONENT`], like `1e9999`. Always a multiple
+    /// of 10.
+    Huge,
+    /// Any other integer of more than [`MAX_DIGITS`] digits
+    TooLong,
+}
+
+/// Classify `number` exactly, without going through a float.
+pub fn classify(number: &Number) -> Kind {
+    // `arbitrary_precision` keeps the number as written, which serde_json validated
+    let text = number.to_string();
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => {
+            // Out of range exponents are far beyond the digit limit anyway
+            let exponent = exponent.trim_start_matches('+');
+            let exponent = exponent
+                .parse::<i64>()
+                .unwrap_or(if exponent.starts_with('-') {
+                    i64::MIN
+                } else {
+                    i64::MAX
+                });
+            (mantissa, exponent)
+        }
+        None => (text.as_str(), 0),
+    };
+    let (negative, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => (true, mantissa),
+        None => (false, mantissa),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+    // The number is `digits * 10^shift`, with `digits` not ending with a 0
+    let digits = format!("{whole}{fraction}");
+    let digits = digits.trim_start_matches('0');
+    let significant = digits.trim_end_matches('0');
+    if significant.is_empty() {
+        // Zero, including -0 and 0.0e5
+        return Kind::Integer(BigInt::default());
+    }
+    let trailing_zeros = (digits.len() - significant.len()) as i128;
+    let shift = exponent as i128 - fraction.len() as i128 + trailing_zeros;
+
+    if shift < 0 {
+        // Dividing by a power of 10 what is not a multiple of 10
+        return Kind::Fraction;
+    }
+    if exponent as i128 - fraction.len() as i128 > MAX_EXPONENT {
+        return Kind::Huge;
+    }
+    if significant.len() as i128 + shift > MAX_DIGITS {
+        return Kind::TooLong;
+    }
+    let integer = BigInt::from_str(significant).expect("digits are a valid integer")
+        * BigInt::from(10).pow(shift as u32);
+    Kind::Integer(if negative { -integer } else { integer })
+}
+
+/// Why a [`Kind::TooLong`] integer is refused. Not quoting it, as it is that long.
+pub fn too_long() -> String {
+    format!("integer has more than {MAX_DIGITS} digits")
+}
+
+/// Integer of any size, read from and written as a plain JSON number.
+///
+/// Numbers like `5.0` or `1e3` are read as integers too. The `serde` support of [`BigInt`]
+/// uses a list of digits instead.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Integer(pub BigInt);
+
+impl From<BigInt> for Integer {
+    fn from(number: BigInt) -> Self {
+        Self(number)
+    }
+}
+
+impl Display for Integer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Serialize for Integer {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        // Any string of digits is a valid number with `arbitrary_precision`
+        Number::from_str(&self.0.to_string())
+            .expect("integers are valid JSON numbers")
+            .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Integer {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let number = Number::deserialize(deserializer)?;
+        match classify(&number) {
+            Kind::Integer(integer) => Ok(Self(integer)),
+            Kind::Fraction => Err(D::Error::custom(format!(
+                "expected an integer, got {number}"
+            ))),
+            Kind::Huge => Err(D::Error::custom(format!(
+                "integer {number} has an exponent over {MAX_EXPONENT}"
+            ))),
+            Kind::TooLong => Err(D::Error::custom(too_long())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify_str(number: &str) -> Kind {
+        classify(&Number::from_str(number).unwrap())
+    }
+
+    #[test]
+    fn test_classify() {
+        let integer = |number: i64| Kind::Integer(BigInt::from(number));
+        for (number, kind) in [
+            ("0", integer(0)),
+            ("-0", integer(0)),
+            ("-0.0", integer(0)),
+            ("0e-999999999999999999999", integer(0)),
+            ("7", integer(7)),
+            ("-7", integer(-7)),
+            ("5.0", integer(5)),
+            ("5.000", integer(5)),
+            ("-5.0", integer(-5)),
+            ("1e3", integer(1000)),
+            ("1E3", integer(1000)),
+            ("1e+3", integer(1000)),
+            ("1.5e1", integer(15)),
+            ("1.25e2", integer(125)),
+            ("100e-2", integer(1)),
+            ("12300e-2", integer(123)),
+            ("-2.50e1", integer(-25)),
+            ("0.5", Kind::Fraction),
+            ("1.5", Kind::Fraction),
+            ("3969458.1234", Kind::Fraction),
+            ("1e-1", Kind::Fraction),
+            ("101e-2", Kind::Fraction),
+            ("1.25e1", Kind::Fraction),
+            ("-0.001", Kind::Fraction),
+            ("1e-999999999999999999999", Kind::Fraction),
+            ("1e4096", Kind::Integer(BigInt::from(10).pow(4096u32))),
+            ("0.1e4097", Kind::Integer(BigInt::from(10).pow(4096u32))),
+            ("1e4097", Kind::Huge),
+            ("1e999999", Kind::Huge),
+            ("-1e99999999999999999999", Kind::Huge),
+        ] {
+            assert_eq!(classify_str(number), kind, "{number}");
+        }
+    }
+
+    #[test]
+    fn test_classify_long_integers() {
+        let nines = "9".repeat(MAX_DIGITS as usize);
+        assert!(matches!(classify_str(&nines), Kind::Integer(_)));
+        assert_eq!(classify_str(&format!("{nines}9")), Kind::TooLong);
+        assert_eq!(classify_str(&format!("-{nines}9")), Kind::TooLong);
+        // Trailing zeros and exponents count too
+        assert_eq!(classify_str(&format!("{nines}0")), Kind::TooLong);
+        assert_eq!(classify_str(&format!("{nines}e1")), Kind::TooLong);
+        assert_eq!(classify_str(&format!("{nines}.5e1")), Kind::TooLong);
+        // But a fraction is a fraction, however long
+        assert_eq!(classify_str(&format!("{nines}.5")), Kind::Fraction);
+    }
+
+    #[test]
+    fn test_classify_big_integers() {
+        let expected = BigInt::from_str("170141183460469231731687303715884105727").unwrap();
+        assert_eq!(
+            classify_str("170141183460469231731687303715884105727"),
+            Kind::Integer(expected.clone())
+        );
+        assert_eq!(
+            classify_str("1.70141183460469231731687303715884105727e38"),
+            Kind::Integer(expected)
+        );
+        assert_eq!(
+            classify_str("1e40"),
+            Kind::Integer(BigInt::from(10).pow(40u32))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let json = "[-3,0,170141183460469231731687303715884105727]";
+        let integers: Vec<Integer> = serde_json::from_str(json).unwrap();
+        assert_eq!(integers[0], Integer(BigInt::from(-3)));
+        assert_eq!(serde_json::to_string(&integers).unwrap(), json);
+    }
+
+    #[test]
+    fn test_integer_notations() {
+        let integers: Vec<Integer> = serde_json::from_str("[5.0,1e3,-0]").unwrap();
+        assert_eq!(serde_json::to_string(&integers).unwrap(), "[5,1000,0]");
+    }
+
+    #[test]
+    fn test_not_an_integer() {
+        let too_long = "7".repeat(MAX_DIGITS as usize + 1);
+        for json in ["1.5", "1e-3", "1e999999", &too_long, "\"7\"", "null"] {
+            assert!(serde_json::from_str::<Integer>(json).is_err(), "{json}");
+        }
+    }
+}
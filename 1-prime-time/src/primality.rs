@@ -0,0 +1,412 @@
+//! Primality tests.
+//!
+//! Numbers that fit in a `u64` get a deterministic answer. Bigger ones go through the
+//! Baillie–PSW test, which has no known counterexample.
+
+use num_bigint::{BigInt, BigUint, Sign};
+use num_integer::Integer;
+use num_traits::{One, ToPrimitive, Zero};
+
+use crate::deadline::Deadline;
+
+/// Testing these bases is enough to make Miller–Rabin deterministic below 2^64
+const U64_WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Primes used to quickly rule out most composites before the expensive tests
+const SMALL_PRIMES: [u64; 25] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+];
+
+/// Most witnesses [`is_probable_prime_with_rounds`] can use
+pub const MAX_ROUNDS: usize = SMALL_PRIMES.len();
+
+/// Whether `number` is prime. Negative numbers, 0 and 1 are not.
+pub fn is_prime(number: &BigInt) -> bool {
+    match number.sign() {
+        Sign::Minus | Sign::NoSign => false,
+        Sign::Plus => is_prime_biguint(number.magnitude()),
+    }
+}
+
+fn is_prime_biguint(number: &BigUint) -> bool {
+    match number.to_u64() {
+        Some(number) => is_prime_u64(number),
+        None => is_probable_prime(number),
+    }
+}
+
+/// Smallest prime greater than `number`, or `None` if still not found once `deadline` passed.
+pub fn next_prime(number: &BigInt, deadline: &Deadline) -> Option<BigInt> {
+    let two = BigInt::from(2);
+    if *number < two {
+        return Some(two);
+    }
+    // Only odd candidates from here on
+    let mut candidate = number + if number.is_even() { 1 } else { 2 };
+    while !is_prime(&candidate) {
+        if deadline.passed() {
+            return None;
+        }
+        candidate += 2;
+    }
+    Some(candidate)
+}
+
+/// Miller–Rabin with the first `rounds` primes as witnesses, up to [`MAX_ROUNDS`].
+///
+/// A composite answer is always right. A prime one may not be, unlike [`is_prime`].
+pub fn is_probable_prime_with_rounds(number: &BigInt, rounds: usize) -> bool {
+    let Some(number) = number.to_biguint() else {
+        return false;
+    };
+    if number < BigUint::from(2u32) {
+        return false;
+    }
+    for prime in SMALL_PRIMES {
+        if (&number % prime).is_zero() {
+            return number == BigUint::from(prime);
+        }
+    }
+    // Past trial division, `number` is greater than every witness
+    SMALL_PRIMES[..rounds.min(MAX_ROUNDS)]
+        .iter()
+        .all(|&witness| is_strong_probable_prime(&number, &BigUint::from(witness)))
+}
+
+/// Deterministic Miller–Rabin.
+pub fn is_prime_u64(number: u64) -> bool {
+    if number < 2 {
+        return false;
+    }
+    for prime in SMALL_PRIMES {
+        if number.is_multiple_of(prime) {
+            return number == prime;
+        }
+    }
+
+    let (d, s) = split_power_of_two_u64(number - 1);
+    U64_WITNESSES
+        .iter()
+        .all(|&witness| is_strong_probable_prime_u64(number, witness, d, s))
+}
+
+/// Miller–Rabin round: whether `number` looks prime to `witness`, with `number - 1 = d * 2^s`.
+fn is_strong_probable_prime_u64(number: u64, witness: u64, d: u64, s: u32) -> bool {
+    let mut x = pow_mod_u64(witness % number, d, number);
+    if x == 1 || x == number - 1 {
+        return true;
+    }
+    for _ in 1..s {
+        x = mul_mod_u64(x, x, number);
+        if x == number - 1 {
+            return true;
+        }
+    }
+    false
+}
+
+fn mul_mod_u64(a: u64, b: u64, modulus: u64) -> u64 {
+    (a as u128 * b as u128 % modulus as u128) as u64
+}
+
+fn pow_mod_u64(mut base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result = 1;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod_u64(result, base, modulus);
+        }
+        base = mul_mod_u64(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Returns `(d, s)` such that `number = d * 2^s` with `d` odd.
+fn split_power_of_two_u64(number: u64) -> (u64, u32) {
+    let s = number.trailing_zeros();
+    (number >> s, s)
+}
+
+/// Baillie–PSW: a base 2 Miller–Rabin round followed by a strong Lucas test.
+///
+/// Deterministic below 2^64 and with no known counterexample above.
+pub fn is_probable_prime(number: &BigUint) -> bool {
+    if let Some(number) = number.to_u64() {
+        return is_prime_u64(number);
+    }
+    for prime in SMALL_PRIMES {
+        if (number % prime).is_zero() {
+            return false;
+        }
+    }
+    is_strong_probable_prime(number, &BigUint::from(2u32)) && is_strong_lucas_probable_prime(number)
+}
+
+/// Miller–Rabin round for an odd `number` greater than `witness`.
+fn is_strong_probable_prime(number: &BigUint, witness: &BigUint) -> bool {
+    let number_minus_one = number - 1u32;
+    let s = number_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &number_minus_one >> s;
+
+    let mut x = witness.modpow(&d, number);
+    if x.is_one() || x == number_minus_one {
+        return true;
+    }
+    for _ in 1..s {
+        x = &x * &x % number;
+        if x == number_minus_one {
+            return true;
+        }
+    }
+    false
+}
+
+/// Strong Lucas test with the parameters of Selfridge's method A, for an odd `number`.
+fn is_strong_lucas_probable_prime(number: &BigUint) -> bool {
+    // No D below can give a Jacobi symbol of -1 for a perfect square
+    if number.sqrt().pow(2) == *number {
+        return false;
+    }
+
+    // First D in 5, -7, 9, -11, 13... with (D/n) = -1
+    let mut d = 5i64;
+    loop {
+        match jacobi(d, number) {
+            -1 => break,
+            // `number` shares a factor with D. It is prime only if it is that factor.
+            0 if BigUint::from(d.unsigned_abs()) != *number => return false,
+            _ => {}
+        }
+        d = if d > 0 { -(d + 2) } else { -d + 2 };
+    }
+
+    let number = BigInt::from(number.clone());
+    let d = BigInt::from(d);
+    let p = BigInt::one();
+    let q: BigInt = (BigInt::one() - &d) / 4;
+
+    // number + 1 = k * 2^s, with k odd
+    let number_plus_one = &number + 1u32;
+    let s = number_plus_one.trailing_zeros().unwrap_or(0);
+    let k = &number_plus_one >> s;
+
+    // Compute U_k, V_k and Q^k walking the bits of k from the top
+    let reduce = |value: BigInt| value.mod_floor(&number);
+    let halve = |value: BigInt| {
+        let value = if value.is_odd() {
+            value + &number
+        } else {
+            value
+        };
+        reduce(value / 2)
+    };
+    let mut u = BigInt::one();
+    let mut v = p.clone();
+    let mut q_k = reduce(q.clone());
+    for bit in (0..k.bits() - 1).rev() {
+        // Double the index
+        u = reduce(&u * &v);
+        v = reduce(&v * &v - 2 * &q_k);
+        q_k = reduce(&q_k * &q_k);
+        if k.bit(bit) {
+            // Increment the index
+            let next_u = halve(&p * &u + &v);
+            v = halve(&d * &u + &p * &v);
+            u = next_u;
+            q_k = reduce(q_k * &q);
+        }
+    }
+
+    if u.is_zero() || v.is_zero() {
+        return true;
+    }
+    // V_(k * 2^r) for r in 1..s
+    for _ in 1..s {
+        v = reduce(&v * &v - 2 * &q_k);
+        if v.is_zero() {
+            return true;
+        }
+        q_k = reduce(&q_k * &q_k);
+    }
+    false
+}
+
+/// Jacobi symbol (a/n) for an odd positive `n`.
+fn jacobi(a: i64, n: &BigUint) -> i8 {
+    let mut a = BigInt::from(a).mod_floor(&BigInt::from(n.clone()));
+    let mut n = BigInt::from(n.clone());
+    let mut result = 1;
+    while !a.is_zero() {
+        while a.is_even() {
+            a >>= 1;
+            let n_mod_8 = (&n % 8u32).to_u8().unwrap();
+            if n_mod_8 == 3 || n_mod_8 == 5 {
+                result = -result;
+            }
+        }
+        std::mem::swap(&mut a, &mut n);
+        if (&a % 4u32).to_u8() == Some(3) && (&n % 4u32).to_u8() == Some(3) {
+            result = -result;
+        }
+        a = a.mod_floor(&n);
+    }
+    if n.is_one() {
+        result
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use num_traits::Pow;
+
+    use super::*;
+    use crate::deadline::Cancellation;
+
+    fn big(number: &str) -> BigInt {
+        BigInt::from_str(number).unwrap()
+    }
+
+    fn mersenne(exponent: u32) -> BigInt {
+        (BigInt::one() << exponent) - 1
+    }
+
+    #[test]
+    fn test_small_numbers() {
+        let primes: Vec<u64> = (0..200).filter(|&n| is_prime_u64(n)).collect();
+        assert_eq!(
+            primes,
+            [
+                2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79,
+                83, 89, 97, 101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167,
+                173, 179, 181, 191, 193, 197, 199
+            ]
+        );
+    }
+
+    #[test]
+    fn test_u64() {
+        // Largest primes below 2^32 and 2^64
+        assert!(is_prime_u64(4_294_967_291));
+        assert!(is_prime_u64(18_446_744_073_709_551_557));
+        assert!(!is_prime_u64(u64::MAX));
+        // Carmichael numbers
+        for carmichael in [561, 41_041, 825_265, 321_197_185] {
+            assert!(!is_prime_u64(carmichael), "{carmichael}");
+        }
+        // Strong pseudoprimes to several of the first bases
+        for pseudoprime in [2_047, 1_373_653, 3_215_031_751, 3_825_123_056_546_413_051] {
+            assert!(!is_prime_u64(pseudoprime), "{pseudoprime}");
+        }
+        // Product of the two largest primes below 2^32
+        assert!(!is_prime_u64(4_294_967_291 * 4_294_967_279));
+    }
+
+    #[test]
+    fn test_big_primes() {
+        for exponent in [61, 89, 107, 127, 521, 607] {
+            assert!(is_prime(&mersenne(exponent)), "2^{exponent} - 1");
+        }
+        // Smallest prime above 10^30 and 10^50
+        assert!(is_prime(&big("1000000000000000000000000000057")));
+        assert!(is_prime(&big(
+            "100000000000000000000000000000000000000000000000151"
+        )));
+    }
+
+    #[test]
+    fn test_big_composites() {
+        for exponent in [67, 101, 256, 523] {
+            assert!(!is_prime(&mersenne(exponent)), "2^{exponent} - 1");
+        }
+        // Product of two Mersenne primes
+        assert!(!is_prime(&(mersenne(89) * mersenne(107))));
+        // Square of a prime, which the Lucas test must reject on its own
+        assert!(!is_prime(&(mersenne(61) * mersenne(61))));
+        assert!(!is_prime(&big(
+            "33373922058321534863170207542241913960201772570479492638113619"
+        )));
+        assert!(!is_prime(&big("-170141183460469231731687303715884105727")));
+    }
+
+    #[test]
+    fn test_strong_lucas_pseudoprimes() {
+        // Primes always pass
+        for prime in (101..5_000u32).filter(|&n| is_prime_u64(n as u64)) {
+            assert!(
+                is_strong_lucas_probable_prime(&BigUint::from(prime)),
+                "{prime}"
+            );
+        }
+        // Fool the Lucas test alone, but not Baillie–PSW
+        for pseudoprime in [5_459u32, 5_777, 10_877, 16_109, 18_971] {
+            let pseudoprime = BigUint::from(pseudoprime);
+            assert!(is_strong_lucas_probable_prime(&pseudoprime));
+            assert!(!is_strong_probable_prime(
+                &pseudoprime,
+                &BigUint::from(2u32)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_next_prime() {
+        let next_prime = |number: &BigInt| {
+            next_prime(
+                number,
+                &Deadline::after(Duration::from_secs(10), &Cancellation::default()),
+            )
+            .unwrap()
+        };
+        let next = |number: i64| next_prime(&BigInt::from(number));
+        assert_eq!(next(-10), BigInt::from(2));
+        assert_eq!(next(2), BigInt::from(3));
+        assert_eq!(next(3), BigInt::from(5));
+        assert_eq!(next(24), BigInt::from(29));
+        assert_eq!(next(4_294_967_279), BigInt::from(4_294_967_291u64));
+        assert_eq!(
+            next_prime(&big("1000000000000000000000000000000")),
+            big("1000000000000000000000000000057")
+        );
+    }
+
+    #[test]
+    fn test_next_prime_deadline() {
+        let huge = BigInt::from(10).pow(1000u32);
+        assert_eq!(
+            next_prime(
+                &huge,
+                &Deadline::after(Duration::ZERO, &Cancellation::default())
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_probable_prime_with_rounds() {
+        assert!(is_probable_prime_with_rounds(&BigInt::from(97), 1));
+        assert!(!is_probable_prime_with_rounds(&BigInt::from(-7), 1));
+        assert!(is_probable_prime_with_rounds(&mersenne(127), 5));
+        // Strong pseudoprime to the bases 2, 3, 5 and 7
+        assert!(is_probable_prime_with_rounds(
+            &BigInt::from(3_215_031_751u64),
+            4
+        ));
+        assert!(!is_probable_prime_with_rounds(
+            &BigInt::from(3_215_031_751u64),
+            5
+        ));
+    }
+
+    #[test]
+    fn test_jacobi() {
+        let n = BigUint::from(45u32);
+        let symbols: Vec<i8> = (1..=10).map(|a| jacobi(a, &n)).collect();
+        assert_eq!(symbols, [1, -1, 0, 1, 0, 0, -1, -1, 0, 0]);
+        assert_eq!(jacobi(-7, &BigUint::from(13u32)), -1);
+    }
+}
@@ -1,14 +1,76 @@
-use crate::connection;
+use std::sync::Arc;
+
 use anyhow::Result;
 use tokio::net::TcpListener;
+use tokio::sync::Semaphore;
 
-pub async fn run(listener: TcpListener) -> Result<()> {
-    loop {
-        let (stream, _address) = listener.accept().await?;
+use protohackers_core::server::Server;
+
+use crate::connection;
+use crate::handler::{self, Registry};
+
+/// Longest request line accepted by default, newline excluded
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Big numbers whose primality is remembered by default
+pub const DEFAULT_CACHE_SIZE: usize = 10_000;
+
+/// Numbers below this are looked up in the sieve by default
+pub const DEFAULT_SIEVE_BOUND: u64 = 10_000_000;
 
-        tokio::spawn(async move {
-            connection::handle_connection(stream).await?;
-            Ok::<(), anyhow::Error>(())
-        });
+/// Requests handled at the same time across every connection by default
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 32;
+
+/// Tunables of the protocol, on top of those of the accept loop.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Clients sending a longer line, newline excluded, get an error and are disconnected
+    pub max_frame_size: usize,
+    /// Reject requests with fields their method doesn't take, instead of ignoring them
+    pub strict: bool,
+    /// How many `isPrime` results for numbers between 2^64 and 2^4096 are remembered.
+    /// 0 disables the cache.
+    pub cache_size: usize,
+    /// Numbers below this are answered from a sieve computed at startup. 0 disables it.
+    pub sieve_bound: u64,
+    /// Requests handled at the same time across every connection, the others waiting for
+    /// their turn. At least 1.
+    pub max_concurrent_requests: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            strict: false,
+            cache_size: DEFAULT_CACHE_SIZE,
+            sieve_bound: DEFAULT_SIEVE_BOUND,
+            max_concurrent_requests: DEFAULT_MAX_CONCURRENT_REQUESTS,
+        }
     }
 }
+
+pub async fn run(listener: TcpListener) -> Result<()> {
+    serve(Server::new(listener), Options::default()).await
+}
+
+/// Serve connections on an already configured `server` until it is shut down.
+pub async fn serve(server: Server, options: Options) -> Result<()> {
+    let idle_timeout = server.config().idle_timeout;
+    let registry = Arc::new(Registry::with_default_methods(&options));
+    let permits = Arc::new(Semaphore::new(options.max_concurrent_requests.max(1)));
+    handler::register_metrics();
+    server
+        .with_reject_handler(connection::reject_connection)
+        .run(move |stream, _address| {
+            connection::handle_connection(
+                stream,
+                idle_timeout,
+                options.max_frame_size,
+                Arc::clone(&registry),
+                Arc::clone(&permits),
+            )
+        })
+        .await;
+    Ok(())
+}
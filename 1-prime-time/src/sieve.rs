@@ -0,0 +1,176 @@
+//! Segmented sieve of Eratosthenes answering questions about small numbers in constant time.
+
+/// Bits sieved at a time, so that the segment stays in the CPU cache
+const SEGMENT_BITS: u64 = 1 << 18;
+
+/// Primes below a bound, computed once.
+pub struct Sieve {
+    bound: u64,
+    /// Bit `i` tells whether the odd number `2i + 1` is prime
+    odd: Vec<u64>,
+    /// Odd primes in the words before each word of `odd`
+    counts: Vec<u32>,
+}
+
+impl Sieve {
+    /// Sieve the numbers below `bound`.
+    pub fn new(bound: u64) -> Self {
+        // Odd numbers below the bound
+        let bits = bound / 2;
+        let mut odd = vec![!0u64; bits.div_ceil(64) as usize];
+
+        let base_primes = small_odd_primes(bound.isqrt());
+        for start in (0..bits).step_by(SEGMENT_BITS as usize) {
+            let end = (start + SEGMENT_BITS).min(bits);
+            // Numbers 2 * start + 1 up to 2 * end - 1
+            let low = 2 * start + 1;
+            for &prime in &base_primes {
+                if prime * prime > 2 * end {
+                    break;
+                }
+                // First odd multiple in the segment, not below the square
+                let mut multiple = (prime * prime).max(low.div_ceil(prime) * prime);
+                if multiple.is_multiple_of(2) {
+                    multiple += prime;
+                }
+                for index in (multiple / 2..end).step_by(prime as usize) {
+                    odd[(index / 64) as usize] &= !(1 << (index % 64));
+                }
+            }
+        }
+        if bits > 0 {
+            // 1 is not prime
+            odd[0] &= !1;
+        }
+        // Clear the bits past the bound in the last word
+        if !bits.is_multiple_of(64) {
+            *odd.last_mut().unwrap() &= (1 << (bits % 64)) - 1;
+        }
+
+        let mut counts = Vec::with_capacity(odd.len());
+        let mut count = 0;
+        for word in &odd {
+            counts.push(count);
+            count += word.count_ones();
+        }
+        Self { bound, odd, counts }
+    }
+
+    /// Numbers below this are answered by the sieve.
+    pub fn bound(&self) -> u64 {
+        self.bound
+    }
+
+    /// Whether `number` is prime, or `None` if it is not below the bound.
+    pub fn is_prime(&self, number: u64) -> Option<bool> {
+        if number >= self.bound {
+            return None;
+        }
+        Some(match number {
+            2 => true,
+            _ if number.is_multiple_of(2) => false,
+            _ => self.bit(number / 2),
+        })
+    }
+
+    /// Smallest prime greater than `number`, or `None` if it is not below the bound.
+    pub fn next_prime(&self, number: u64) -> Option<u64> {
+        if number < 2 {
+            return (self.bound > 2).then_some(2);
+        }
+        let next_odd = if number.is_multiple_of(2) {
+            number + 1
+        } else {
+            number + 2
+        };
+        let mut index = next_odd / 2;
+        let bits = self.bound / 2;
+        while index < bits {
+            let word = self.odd[(index / 64) as usize] >> (index % 64);
+            if word != 0 {
+                let prime = 2 * (index + u64::from(word.trailing_zeros())) + 1;
+                return (prime < self.bound).then_some(prime);
+            }
+            index = (index / 64 + 1) * 64;
+        }
+        None
+    }
+
+    /// How many primes are less than or equal to `number`, or `None` if it is not below the
+    /// bound.
+    pub fn prime_count(&self, number: u64) -> Option<u64> {
+        if number >= self.bound {
+            return None;
+        }
+        if number < 2 {
+            return Some(0);
+        }
+        // Odd numbers up to `number` have the indexes 0 to `last`
+        let last = (number - 1) / 2;
+        let word = (last / 64) as usize;
+        let mask = !0u64 >> (63 - last % 64);
+        let odd_primes = self.counts[word] + (self.odd[word] & mask).count_ones();
+        // Plus 2
+        Some(u64::from(odd_primes) + 1)
+    }
+
+    fn bit(&self, index: u64) -> bool {
+        self.odd[(index / 64) as usize] & (1 << (index % 64)) != 0
+    }
+}
+
+/// Odd primes up to `limit`, with a plain sieve.
+fn small_odd_primes(limit: u64) -> Vec<u64> {
+    let mut composite = vec![false; limit as usize + 1];
+    let mut primes = Vec::new();
+    for number in (3..=limit).step_by(2) {
+        if composite[number as usize] {
+            continue;
+        }
+        primes.push(number);
+        for multiple in (number * number..=limit).step_by(2 * number as usize) {
+            composite[multiple as usize] = true;
+        }
+    }
+    primes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primality::is_prime_u64;
+
+    #[test]
+    fn test_matches_miller_rabin() {
+        // Bounds around a segment and word boundaries
+        for bound in [0, 1, 2, 3, 4, 100, 2 * SEGMENT_BITS + 129, 1_000_003] {
+            let sieve = Sieve::new(bound);
+            let mut count = 0;
+            let mut next = None;
+            for number in (0..bound).rev() {
+                let prime = is_prime_u64(number);
+                assert_eq!(sieve.is_prime(number), Some(prime), "{number} < {bound}");
+                assert_eq!(sieve.next_prime(number), next, "after {number} < {bound}");
+                if prime {
+                    next = Some(number);
+                }
+            }
+            for number in 0..bound {
+                count += u64::from(is_prime_u64(number));
+                assert_eq!(sieve.prime_count(number), Some(count), "{number} < {bound}");
+            }
+            assert_eq!(sieve.is_prime(bound), None);
+            assert_eq!(sieve.prime_count(bound), None);
+        }
+    }
+
+    #[test]
+    fn test_prime_count() {
+        let sieve = Sieve::new(10_000_001);
+        assert_eq!(sieve.prime_count(10), Some(4));
+        assert_eq!(sieve.prime_count(1_000), Some(168));
+        assert_eq!(sieve.prime_count(1_000_000), Some(78_498));
+        assert_eq!(sieve.prime_count(10_000_000), Some(664_579));
+        assert_eq!(sieve.next_prime(9_999_991), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use prime_time::{client, server};
+
+async fn start_server() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move { server::run(listener).await });
+
+    addr
+}
+
+#[tokio::test]
+async fn test_is_prime() {
+    let server = start_server().await;
+    let numbers = client::parse_numbers("1\n2\n7.0\n8\n-7\n3.5\n2305843009213693951\n").unwrap();
+    let expected = [false, true, true, false, false, false, true];
+
+    for pipeline in [1, 3, 100] {
+        let primes = client::is_prime(server, &numbers, pipeline).await.unwrap();
+        assert_eq!(primes, expected, "pipeline {pipeline}");
+    }
+}
+
+#[tokio::test]
+async fn test_fuzz() {
+    let server = start_server().await;
+
+    let report = client::fuzz(server, client::DEFAULT_FUZZ_TIMEOUT)
+        .await
+        .unwrap();
+    assert!(report.is_success(), "{report}");
+    assert_eq!(report.passed, client::fuzz_cases().len());
+}
+
+#[tokio::test]
+async fn test_fuzz_detects_open_connection() {
+    // Answers every line with an error but never closes the connection
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                let mut first = true;
+                while let Ok(Some(_)) = lines.next_line().await {
+                    let response: &[u8] = if first {
+                        b"{\"method\":\"isPrime\",\"prime\":true}\n"
+                    } else {
+                        b"{\"error\":\"invalid json\"}\n"
+                    };
+                    first = false;
+                    writer.write_all(response).await.unwrap();
+                }
+                std::future::pending::<()>().await;
+            });
+        }
+    });
+
+    let report = client::fuzz(server, Duration::from_millis(50))
+        .await
+        .unwrap();
+    assert_eq!(report.passed, 0);
+    assert!(report
+        .failures
+        .iter()
+        .all(|(_, failure)| failure == "Timed out"));
+}
+
+#[tokio::test]
+async fn test_is_prime_window_larger_than_socket_buffers() {
+    // Answers each request as soon as it is read, before reading the next one, with a
+    // response padded to fill the socket buffers quickly
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let response = format!(
+            "{}{{\"method\":\"isPrime\",\"prime\":true}}\n",
+            " ".repeat(100_000)
+        );
+        while let Ok(Some(_)) = lines.next_line().await {
+            writer.write_all(response.as_bytes()).await.unwrap();
+        }
+    });
+
+    // 7, padded too, so that megabytes of requests are in flight at once
+    let seven = format!("7.{}", "0".repeat(100_000));
+    let numbers = client::parse_numbers(&format!("{seven}\n").repeat(100)).unwrap();
+    let primes = tokio::time::timeout(
+        Duration::from_secs(30),
+        client::is_prime(server, &numbers, numbers.len()),
+    )
+    .await
+    .expect("the client stopped reading responses while sending requests")
+    .unwrap();
+    assert_eq!(primes, [true; 100]);
+}
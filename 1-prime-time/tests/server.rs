@@ -1,9 +1,12 @@
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
 
 use tokio::net::{TcpListener, TcpStream};
 
 use prime_time::server;
+use protohackers_core::server::{Config, Server};
 
 async fn start_server() -> SocketAddr {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -66,3 +69,217 @@ async fn test_invalid_method() {
         String::from_utf8(response.to_vec()).unwrap(),
     );
 }
+
+#[tokio::test]
+async fn test_other_methods() {
+    let server = start_server().await;
+    let connection = TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    let input = b"{\"method\":\"gcd\",\"numbers\":[12,18]}\n\
+                  {\"method\":\"nextPrime\",\"number\":7}\n\
+                  {\"method\":\"gcd\",\"numbers\":[]}\n";
+    writer.write_all(input).await.unwrap();
+
+    let mut responses = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        responses.push(line);
+    }
+    // The connection is closed after the invalid request
+    assert_eq!(
+        responses,
+        [
+            r#"{"method":"gcd","gcd":6}"#,
+            r#"{"method":"nextPrime","number":11}"#,
+            r#"{"error":"invalid params: numbers is empty"}"#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_responses_in_request_order() {
+    let server = start_server().await;
+    let connection = TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // Takes a while: the product of two 31 digit primes is out of reach of Pollard's rho
+    let slow = b"{\"method\":\"factorize\",\"number\":1000000000000000000000000000156000000000000000000000000005643}\n";
+    writer.write_all(slow).await.unwrap();
+    writer
+        .write_all(b"{\"method\":\"isPrime\",\"number\":7}\n")
+        .await
+        .unwrap();
+
+    let first = lines.next_line().await.unwrap().unwrap();
+    assert!(first.starts_with(r#"{"method":"factorize""#), "{first}");
+    let second = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(second, r#"{"method":"isPrime","prime":true}"#);
+}
+
+#[tokio::test]
+async fn test_pipelined_requests() {
+    let server = start_server().await;
+    let connection = TcpStream::connect(server).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // Many more requests than can be in flight at once
+    let numbers = 0..1_000;
+    let requests: String = numbers
+        .clone()
+        .map(|number| format!("{{\"method\":\"nextPrime\",\"number\":{number}}}\n"))
+        .collect();
+    tokio::spawn(async move { writer.write_all(requests.as_bytes()).await.unwrap() });
+
+    let mut previous = 0;
+    for number in numbers {
+        let line = lines.next_line().await.unwrap().unwrap();
+        let response: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let next_prime = response["number"].as_u64().unwrap();
+        assert!(
+            next_prime > number && next_prime >= previous,
+            "{number}: {line}"
+        );
+        previous = next_prime;
+    }
+}
+
+#[tokio::test]
+async fn test_frame_too_long() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = server::Options {
+        max_frame_size: 64,
+        ..Default::default()
+    };
+    tokio::spawn(server::serve(Server::new(listener), options));
+
+    let connection = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    // A request that fits, then a line that never ends
+    writer
+        .write_all(b"{\"method\":\"isPrime\",\"number\":2}\n")
+        .await
+        .unwrap();
+    writer.write_all(&[b' '; 65]).await.unwrap();
+
+    let mut responses = Vec::new();
+    while let Some(line) = lines.next_line().await.unwrap() {
+        responses.push(line);
+    }
+    assert_eq!(
+        responses,
+        [
+            r#"{"method":"isPrime","prime":true}"#,
+            r#"{"error":"request too long"}"#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(listener).with_config(Config {
+        max_connections: Some(1),
+        ..Config::default()
+    });
+    tokio::spawn(server::serve(server, server::Options::default()));
+
+    let connection1 = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = connection1.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"{\"method\":\"isPrime\",\"number\":2}\n")
+        .await
+        .unwrap();
+    let response = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(response, r#"{"method":"isPrime","prime":true}"#);
+
+    // Refused with an error object while the first connection is open
+    let mut connection2 = TcpStream::connect(addr).await.unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        connection2.read_to_string(&mut response),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(response, "{\"error\":\"Too many connections\"}\n");
+}
+
+#[tokio::test]
+async fn test_requests_dropped_with_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = server::Options {
+        max_concurrent_requests: 1,
+        ..Default::default()
+    };
+    tokio::spawn(server::serve(Server::new(listener), options));
+
+    // A malformed request closes the connection, and the slow ones after it are never answered.
+    // Each takes the whole factorize budget of a second, one at a time.
+    let connection = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let slow = "{\"method\":\"factorize\",\"number\":1000000000000000000000000000156000000000000000000000000005643}\n";
+    let requests = format!("{{\n{}", slow.repeat(10));
+    writer.write_all(requests.as_bytes()).await.unwrap();
+    let response = lines.next_line().await.unwrap().unwrap();
+    assert_eq!(response, r#"{"error":"invalid json"}"#);
+
+    // At most waits for the one that had already started
+    let connection = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"{\"method\":\"isPrime\",\"number\":7}\n")
+        .await
+        .unwrap();
+    let response = tokio::time::timeout(Duration::from_secs(3), lines.next_line())
+        .await
+        .expect("requests of the closed connection are still being handled")
+        .unwrap()
+        .unwrap();
+    assert_eq!(response, r#"{"method":"isPrime","prime":true}"#);
+}
+
+#[tokio::test]
+async fn test_disconnected_client_releases_permit() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let options = server::Options {
+        max_concurrent_requests: 1,
+        ..Default::default()
+    };
+    tokio::spawn(server::serve(Server::new(listener), options));
+
+    // Would hold the only permit for the whole factorize budget of a second
+    let mut connection = TcpStream::connect(addr).await.unwrap();
+    let slow = "{\"method\":\"factorize\",\"number\":1000000000000000000000000000156000000000000000000000000005643}\n";
+    connection.write_all(slow.as_bytes()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    // Reset the connection, so that the server notices right away
+    connection.set_zero_linger().unwrap();
+    drop(connection);
+
+    let connection = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = connection.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"{\"method\":\"isPrime\",\"number\":7}\n")
+        .await
+        .unwrap();
+    let response = tokio::time::timeout(Duration::from_millis(500), lines.next_line())
+        .await
+        .expect("the factorization of the closed connection is still running")
+        .unwrap()
+        .unwrap();
+    assert_eq!(response, r#"{"method":"isPrime","prime":true}"#);
+}
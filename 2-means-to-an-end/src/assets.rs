@@ -0,0 +1,302 @@
+//! Price stores of named assets, shared by every connection bound to them.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+
+use anyhow::Context;
+use tokio::task;
+
+use crate::store::{DuplicatePolicy, DuplicateTimestamp, PriceStore};
+use crate::wal::{self, Durability, Wal};
+
+/// The prices of a connection or a named asset, logged to the disk for durable assets.
+///
+/// Queried by any number of connections at once, and written to by one at a time. The prices
+/// are only locked to apply an insert, not while it is being logged.
+#[derive(Debug)]
+pub struct AssetStore {
+    prices: RwLock<PriceStore>,
+    /// Held from logging an insert to applying it, so that the log keeps the order of the
+    /// inserts
+    wal: Option<Mutex<Wal>>,
+}
+
+impl AssetStore {
+    fn new(duplicates: DuplicatePolicy, wal: Option<Wal>) -> Self {
+        Self::recovered(PriceStore::with_policy(duplicates), wal)
+    }
+
+    fn recovered(prices: PriceStore, wal: Option<Wal>) -> Self {
+        Self {
+            prices: RwLock::new(prices),
+            wal: wal.map(Mutex::new),
+        }
+    }
+
+    pub fn prices(&self) -> RwLockReadGuard<'_, PriceStore> {
+        self.prices.read().unwrap()
+    }
+
+    /// Add the `price` at `timestamp`, logging it first on the blocking thread pool if the
+    /// asset is durable.
+    ///
+    /// Fails with a [`DuplicateTimestamp`] when rejected by the duplicate policy.
+    pub async fn insert(self: &Arc<Self>, timestamp: i32, price: i32) -> anyhow::Result<()> {
+        if self.wal.is_none() {
+            return Ok(self.prices.write().unwrap().insert(timestamp, price)?);
+        }
+        let store = Arc::clone(self);
+        task::spawn_blocking(move || store.insert_logged(timestamp, price)).await?
+    }
+
+    fn insert_logged(&self, timestamp: i32, price: i32) -> anyhow::Result<()> {
+        let mut wal = self.wal.as_ref().expect("durable asset").lock().unwrap();
+        // Only inserts change the prices, and they all hold the log
+        let rejected = {
+            let prices = self.prices();
+            prices.policy() == DuplicatePolicy::Reject && prices.contains(timestamp)
+        };
+        if rejected {
+            return Err(DuplicateTimestamp(timestamp).into());
+        }
+        wal.append(timestamp, price)?;
+        self.prices.write().unwrap().insert(timestamp, price)?;
+        if wal.needs_snapshot() {
+            // Written out without holding the prices
+            let prices: Vec<(i32, i32)> = self.prices().prices().collect();
+            if let Err(err) = wal.snapshot(prices) {
+                // The insert is logged and applied all the same. The next one tries again.
+                error!("Could not compact the log of an asset: {err}");
+            }
+        }
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        match &self.wal {
+            Some(wal) => wal.lock().unwrap().sync(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A store shared by every connection bound to the same asset.
+pub type SharedStore = Arc<AssetStore>;
+
+/// A connection bound to a new asset while the server already keeps as many as it can.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooManyAssets(pub usize);
+
+impl Display for TooManyAssets {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "already {} assets", self.0)
+    }
+}
+
+impl std::error::Error for TooManyAssets {}
+
+/// Every named asset a connection bound to, kept for the lifetime of the server, or across
+/// restarts when durable.
+#[derive(Debug)]
+pub struct Assets {
+    duplicates: DuplicatePolicy,
+    durability: Option<Durability>,
+    /// Assets kept at most, as none is ever dropped. Each durable one keeps its log open.
+    max_assets: usize,
+    stores: Mutex<HashMap<String, SharedStore>>,
+}
+
+impl Assets {
+    /// No assets yet, and up to `max_assets` of them. Their stores follow the `duplicates`
+    /// policy, as do private ones.
+    pub fn new(duplicates: DuplicatePolicy, max_assets: usize) -> Self {
+        Self {
+            duplicates,
+            durability: None,
+            max_assets,
+            stores: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The assets logged to the data directory of `durability`, which is created if needed.
+    pub fn open(
+        duplicates: DuplicatePolicy,
+        max_assets: usize,
+        durability: Durability,
+    ) -> anyhow::Result<Self> {
+        let dir = &durability.data_dir;
+        fs::create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+        let mut stores = HashMap::new();
+        for name in wal::asset_names(dir)? {
+            let (wal, prices) = Wal::recover(&durability, &name, duplicates)?;
+            info!("Recovered asset. asset={name}, prices={}", prices.len());
+            stores.insert(name, Arc::new(AssetStore::recovered(prices, Some(wal))));
+        }
+        if stores.len() > max_assets {
+            // Still served, as dropping them would lose their prices
+            warn!(
+                "Recovered more assets than the limit. No new ones will be created. assets={}, \
+                max_assets={max_assets}",
+                stores.len()
+            );
+        }
+        Ok(Self {
+            duplicates,
+            durability: Some(durability),
+            max_assets,
+            stores: Mutex::new(stores),
+        })
+    }
+
+    /// The store of the asset `name`, created empty the first time it is asked for.
+    ///
+    /// Fails with [`TooManyAssets`] for a new asset once there are `max_assets`. Creates the
+    /// log of a new durable asset, so it blocks on the disk.
+    pub fn get(&self, name: &str) -> anyhow::Result<SharedStore> {
+        let mut stores = self.stores.lock().unwrap();
+        if let Some(store) = stores.get(name) {
+            return Ok(Arc::clone(store));
+        }
+        if stores.len() >= self.max_assets {
+            return Err(TooManyAssets(stores.len()).into());
+        }
+        let wal = match &self.durability {
+            Some(durability) => Some(Wal::create(durability, name)?),
+            None => None,
+        };
+        let store = Arc::new(AssetStore::new(self.duplicates, wal));
+        stores.insert(name.to_string(), Arc::clone(&store));
+        Ok(store)
+    }
+
+    /// A new store of a connection not bound to any asset, never logged.
+    pub fn private(&self) -> SharedStore {
+        Arc::new(AssetStore::new(self.duplicates, None))
+    }
+
+    /// Flush the logs of every asset to the disk, blocking on it. The prices can still be
+    /// queried meanwhile.
+    pub fn sync(&self) -> io::Result<()> {
+        let stores: Vec<SharedStore> = self.stores.lock().unwrap().values().cloned().collect();
+        for store in stores {
+            store.sync()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::wal::FsyncPolicy;
+
+    #[tokio::test]
+    async fn test_shared_by_name() {
+        let assets = Assets::new(DuplicatePolicy::KeepBoth, 10);
+        let btc = assets.get("BTC").unwrap();
+        btc.insert(1, 100).await.unwrap();
+
+        assert_eq!(btc.prices().mean(0, 1), 100);
+        assert!(Arc::ptr_eq(&btc, &assets.get("BTC").unwrap()));
+        let eth = assets.get("ETH").unwrap();
+        assert!(eth.prices().is_empty());
+        assert!(assets.private().prices().is_empty());
+    }
+
+    #[test]
+    fn test_max_assets() {
+        let assets = Assets::new(DuplicatePolicy::KeepBoth, 2);
+        let btc = assets.get("BTC").unwrap();
+        assets.get("ETH").unwrap();
+
+        let err = assets.get("DOGE").unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&TooManyAssets(2)));
+        // Those already there are still shared
+        assert!(Arc::ptr_eq(&btc, &assets.get("BTC").unwrap()));
+        assert!(assets.private().prices().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_durable() {
+        let dir = TempDir::new().unwrap();
+        let durability = Durability {
+            data_dir: dir.path().join("data"),
+            fsync: FsyncPolicy::Periodic,
+            snapshot_every: 2,
+        };
+        let assets = Assets::open(DuplicatePolicy::Reject, 10, durability.clone()).unwrap();
+        for (name, timestamp, price) in [("BTC", 1, 100), ("BTC", 2, 200), ("ETH", 1, 10)] {
+            let store = assets.get(name).unwrap();
+            store.insert(timestamp, price).await.unwrap();
+        }
+        // Rejected, so never logged
+        let btc = assets.get("BTC").unwrap();
+        let err = btc.insert(1, 300).await.unwrap_err();
+        assert!(err.is::<DuplicateTimestamp>());
+        assets.private().insert(1, 1).await.unwrap();
+        assets.sync().unwrap();
+        drop(assets);
+
+        let assets = Assets::open(DuplicatePolicy::Reject, 10, durability).unwrap();
+        assert_eq!(assets.stores.lock().unwrap().len(), 2);
+        let btc = assets.get("BTC").unwrap();
+        assert_eq!(btc.prices().mean(0, 10), 150);
+        let eth = assets.get("ETH").unwrap();
+        assert_eq!(eth.prices().mean(0, 10), 10);
+    }
+
+    #[tokio::test]
+    async fn test_failed_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let durability = Durability {
+            data_dir: dir.path().to_path_buf(),
+            fsync: FsyncPolicy::Never,
+            snapshot_every: 2,
+        };
+        let assets = Assets::open(DuplicatePolicy::KeepBoth, 10, durability.clone()).unwrap();
+        let btc = assets.get("BTC").unwrap();
+
+        // In the way of the snapshot
+        let blocker = dir.path().join("BTC.1.snapshot.tmp");
+        fs::create_dir(&blocker).unwrap();
+        btc.insert(1, 100).await.unwrap();
+        btc.insert(2, 200).await.unwrap();
+        assert_eq!(btc.prices().len(), 2);
+
+        fs::remove_dir(&blocker).unwrap();
+        btc.insert(3, 300).await.unwrap();
+        assert!(dir.path().join("BTC.1.snapshot").exists());
+        drop((btc, assets));
+
+        let assets = Assets::open(DuplicatePolicy::KeepBoth, 10, durability).unwrap();
+        assert_eq!(assets.get("BTC").unwrap().prices().mean(0, 10), 200);
+    }
+
+    #[test]
+    fn test_durable_max_assets() {
+        let dir = TempDir::new().unwrap();
+        let durability = Durability {
+            data_dir: dir.path().to_path_buf(),
+            fsync: FsyncPolicy::Never,
+            snapshot_every: 100,
+        };
+        let assets = Assets::open(DuplicatePolicy::KeepBoth, 2, durability.clone()).unwrap();
+        assets.get("BTC").unwrap();
+        assets.get("ETH").unwrap();
+        // Refused before creating its log
+        assert!(assets.get("DOGE").unwrap_err().is::<TooManyAssets>());
+        assert_eq!(wal::asset_names(dir.path()).unwrap().len(), 2);
+        drop(assets);
+
+        // Recovered assets count too, even past a lower limit
+        let assets = Assets::open(DuplicatePolicy::KeepBoth, 1, durability).unwrap();
+        assets.get("BTC").unwrap();
+        assets.get("ETH").unwrap();
+        assert!(assets.get("DOGE").unwrap_err().is::<TooManyAssets>());
+    }
+}
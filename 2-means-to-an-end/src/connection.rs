@@ -1,69 +1,111 @@
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
+use protohackers_core::metrics::{Metered, FRAMES_PARSED, PARSE_ERRORS};
+use protohackers_core::timeout::timeout;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::task;
 
-#[derive(Debug)]
-struct AssetPrice {
-    pub timestamp: i32,
-    pub price: i32,
-}
-
-impl AssetPrice {
-    pub fn new(timestamp: i32, price: i32) -> Self {
-        Self { timestamp, price }
-    }
-}
+use crate::assets::{Assets, TooManyAssets};
+use crate::request::{Aggregate, Request, MESSAGE_SIZE};
+use crate::store::{DuplicateTimestamp, PriceStore};
 
-pub(crate) async fn handle_connection(mut stream: TcpStream) -> Result<()> {
-    let mut buffer = [0; 9];
-    let mut historical_prices = Vec::new();
+pub(crate) async fn handle_connection(
+    stream: TcpStream,
+    session: SocketAddr,
+    idle_timeout: Option<Duration>,
+    assets: Arc<Assets>,
+) -> Result<()> {
+    let mut stream = Metered::new(stream);
+    let mut buffer = [0; MESSAGE_SIZE];
+    let mut historical_prices = assets.private();
+    let mut first_message = true;
     loop {
-        stream.read_exact(&mut buffer).await?;
+        let Ok(read) = timeout(idle_timeout, stream.read_exact(&mut buffer)).await else {
+            info!("Closing idle connection");
+            return Ok(());
+        };
+        match read {
+            Ok(_) => {}
+            // Includes a message cut short by the client closing the connection
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
         debug!("Received message: {:x?}", buffer);
-        match buffer[0] as char {
-            'I' => {
-                let timestamp = i32::from_be_bytes(buffer[1..5].try_into().unwrap());
-                let price = i32::from_be_bytes(buffer[5..9].try_into().unwrap());
-                let asset_price = AssetPrice::new(timestamp, price);
+
+        let request = match Request::parse(&buffer) {
+            Ok(request) => request,
+            Err(err) => {
+                PARSE_ERRORS.inc();
+                warn!("Disconnecting client: {err}. session={session}");
+                return Ok(());
+            }
+        };
+        FRAMES_PARSED.inc();
+        match request {
+            Request::Bind { asset } if first_message => {
+                let assets = Arc::clone(&assets);
+                let name = asset.clone();
+                historical_prices = match task::spawn_blocking(move || assets.get(&name)).await? {
+                    Ok(store) => store,
+                    Err(err) if err.is::<TooManyAssets>() => {
+                        warn!("Disconnecting client: {err}. session={session}, asset={asset}");
+                        return Ok(());
+                    }
+                    Err(err) => return Err(err),
+                };
+                info!("Bound to asset. session={session}, asset={asset}");
+            }
+            Request::Bind { .. } => {
+                warn!("Disconnecting client: bound after other messages. session={session}");
+                return Ok(());
+            }
+            Request::Insert { timestamp, price } => {
                 info!(
                     "Received new price. session={}, timestamp={}, price={}",
-                    stream.peer_addr().unwrap(),
-                    timestamp,
-                    price
+                    session, timestamp, price
                 );
-                historical_prices.push(asset_price);
-                historical_prices.sort_by_key(|asset| asset.timestamp);
-            }
-            'Q' => {
-                let start_time = i32::from_be_bytes(buffer[1..5].try_into().unwrap());
-                let end_time = i32::from_be_bytes(buffer[5..9].try_into().unwrap());
-
-                if start_time > end_time {
-                    // start_time comes after end_time. Must return 0 in this case.
-                    stream.write_i32(0).await?;
-                    continue;
-                }
-
-                let mut prices = Vec::new();
-                for asset_price in &historical_prices {
-                    if asset_price.timestamp < start_time {
-                        continue;
+                match historical_prices.insert(timestamp, price).await {
+                    Ok(()) => {}
+                    Err(err) if err.is::<DuplicateTimestamp>() => {
+                        warn!("Disconnecting client: {err}. session={session}");
+                        return Ok(());
                     }
-                    if asset_price.timestamp > end_time {
-                        break;
-                    }
-                    prices.push(asset_price.price);
-                }
-                if prices.is_empty() {
-                    // There are no prices in the specified time range. Must return 0.
-                    stream.write_i32(0).await?;
-                } else {
-                    let prices_sum = prices.iter().fold(0, |a, &b| a + b as i64);
-                    let mean_price = prices_sum / prices.len() as i64;
-                    stream.write_i32(mean_price as i32).await?;
+                    Err(err) => return Err(err),
                 }
             }
-            _ => panic!(),
+            Request::Query {
+                aggregate,
+                min_time,
+                max_time,
+            } => {
+                // Not holding the lock while writing
+                let answer = {
+                    let prices = historical_prices.prices();
+                    answer(&prices, aggregate, min_time, max_time)
+                };
+                stream.write_i32(answer).await?;
+            }
         }
+        first_message = false;
     }
 }
+
+/// The `aggregate` of the prices between `min_time` and `max_time`, 0 when there are none.
+fn answer(prices: &PriceStore, aggregate: Aggregate, min_time: i32, max_time: i32) -> i32 {
+    let summary = || prices.summary(min_time, max_time);
+    let answer = match aggregate {
+        Aggregate::Mean => summary().mean(),
+        Aggregate::Min => summary().min(),
+        Aggregate::Max => summary().max(),
+        Aggregate::Count => Some(summary().count().min(i32::MAX as u64) as i32),
+        Aggregate::Median => prices.median(min_time, max_time),
+        // Below 2^31, as the prices are in a range of 2^32
+        Aggregate::StdDev => summary().std_dev().map(|std_dev| std_dev as i32),
+    };
+    answer.unwrap_or(0)
+}
@@ -1,5 +1,9 @@
 #[macro_use]
 extern crate log;
 
+pub mod assets;
 mod connection;
+pub mod request;
 pub mod server;
+pub mod store;
+pub mod wal;
@@ -1,13 +1,66 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
+use clap::Parser;
+use log::info;
 use tokio::net::TcpListener;
 
-use means_to_an_end::server::run;
+use means_to_an_end::server;
+use means_to_an_end::store::DuplicatePolicy;
+use means_to_an_end::wal::{self, Durability, FsyncPolicy};
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::server::Server;
+use protohackers_core::{cli, logging, metrics};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(flatten)]
+    server: ServerArgs,
+
+    /// What to do with a price inserted at the timestamp of another one. Clients are
+    /// disconnected when rejected
+    #[arg(long, value_enum, default_value_t = DuplicatePolicy::KeepBoth)]
+    duplicates: DuplicatePolicy,
+
+    /// Named assets kept at most. Clients binding to another one are disconnected
+    #[arg(long, default_value_t = server::DEFAULT_MAX_ASSETS)]
+    max_assets: usize,
+
+    /// Directory keeping the prices of named assets across restarts. In memory only when not
+    /// given
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    /// When the log of the inserts in named assets is flushed to the disk
+    #[arg(long, value_enum, default_value_t = FsyncPolicy::Periodic)]
+    fsync: FsyncPolicy,
+
+    /// Compact the log of a named asset into a snapshot after this many inserts
+    #[arg(long, default_value_t = wal::DEFAULT_SNAPSHOT_EVERY)]
+    snapshot_every: u64,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
+    let cli: Cli = cli::parse(9902);
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
 
-    let listener = TcpListener::bind("0.0.0.0:9902").await?;
+    let listener = TcpListener::bind(cli.server.bind_address()).await?;
+    info!("Starting server at {}", listener.local_addr()?);
 
-    run(listener).await
+    let server = Server::new(listener).with_config(cli.server.server_config());
+    server.shutdown_handle().trigger_on_signal();
+    let options = server::Options {
+        duplicates: cli.duplicates,
+        max_assets: cli.max_assets,
+        durability: cli.data_dir.map(|data_dir| Durability {
+            data_dir,
+            fsync: cli.fsync,
+            snapshot_every: cli.snapshot_every,
+        }),
+    };
+    server::serve(server, options).await
 }
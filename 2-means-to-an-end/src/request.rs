@@ -0,0 +1,166 @@
+use std::fmt::{self, Display, Formatter};
+
+/// Every message is a type byte followed by two big-endian `i32`
+pub const MESSAGE_SIZE: usize = 9;
+
+/// Longest asset name, which fills a message after its type byte
+pub const MAX_ASSET_NAME: usize = MESSAGE_SIZE - 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Request {
+    /// `N`: share the prices of the named asset with the other connections bound to it.
+    /// Only accepted as the first message of a connection.
+    Bind { asset: String },
+    /// `I`: the price of the asset at a timestamp
+    Insert { timestamp: i32, price: i32 },
+    /// An aggregate of the prices between two timestamps, inclusive
+    Query {
+        aggregate: Aggregate,
+        min_time: i32,
+        max_time: i32,
+    },
+}
+
+/// What a query computes, each answered with a single `i32`, 0 when there are no prices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aggregate {
+    /// `Q`: mean, rounded towards zero
+    Mean,
+    /// `L`: lowest price
+    Min,
+    /// `H`: highest price
+    Max,
+    /// `C`: number of prices, up to `i32::MAX`
+    Count,
+    /// `M`: median, the mean of the two middle prices rounded towards zero if there is an even
+    /// number of them
+    Median,
+    /// `S`: population standard deviation, rounded down
+    StdDev,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownType(u8),
+    /// Not ASCII letters, digits, `-`, `_` or `.`, padded with NUL bytes
+    InvalidAssetName,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnknownType(byte) => write!(f, "unknown message type {byte:#04x}"),
+            ParseError::InvalidAssetName => write!(f, "invalid asset name"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Request {
+    pub fn parse(message: &[u8; MESSAGE_SIZE]) -> Result<Self, ParseError> {
+        let first = i32::from_be_bytes(message[1..5].try_into().unwrap());
+        let second = i32::from_be_bytes(message[5..9].try_into().unwrap());
+        match message[0] {
+            b'N' => Ok(Request::Bind {
+                asset: parse_asset_name(&message[1..])?,
+            }),
+            b'I' => Ok(Request::Insert {
+                timestamp: first,
+                price: second,
+            }),
+            byte => {
+                let aggregate = match byte {
+                    b'Q' => Aggregate::Mean,
+                    b'L' => Aggregate::Min,
+                    b'H' => Aggregate::Max,
+                    b'C' => Aggregate::Count,
+                    b'M' => Aggregate::Median,
+                    b'S' => Aggregate::StdDev,
+                    _ => return Err(ParseError::UnknownType(byte)),
+                };
+                Ok(Request::Query {
+                    aggregate,
+                    min_time: first,
+                    max_time: second,
+                })
+            }
+        }
+    }
+}
+
+fn parse_asset_name(bytes: &[u8]) -> Result<String, ParseError> {
+    let length = bytes
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(bytes.len());
+    let (name, padding) = bytes.split_at(length);
+    let valid = |byte: &u8| byte.is_ascii_alphanumeric() || b"-_.".contains(byte);
+    if name.is_empty() || !name.iter().all(valid) || padding.iter().any(|&byte| byte != 0) {
+        return Err(ParseError::InvalidAssetName);
+    }
+    Ok(String::from_utf8(name.to_vec()).expect("ASCII is valid UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Request::parse(&[0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65]),
+            Ok(Request::Insert {
+                timestamp: 12345,
+                price: 101
+            })
+        );
+        assert_eq!(
+            Request::parse(&[0x51, 0x00, 0x00, 0x03, 0xe8, 0xff, 0xff, 0xff, 0xff]),
+            Ok(Request::Query {
+                aggregate: Aggregate::Mean,
+                min_time: 1000,
+                max_time: -1
+            })
+        );
+        assert_eq!(
+            Request::parse(&[b'M', 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02]),
+            Ok(Request::Query {
+                aggregate: Aggregate::Median,
+                min_time: 1,
+                max_time: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_bind() {
+        assert_eq!(
+            Request::parse(b"NBTC-USD\0"),
+            Ok(Request::Bind {
+                asset: "BTC-USD".to_string()
+            })
+        );
+        assert_eq!(
+            Request::parse(b"Nabcdefgh"),
+            Ok(Request::Bind {
+                asset: "abcdefgh".to_string()
+            })
+        );
+        for message in [
+            b"N\0\0\0\0\0\0\0\0",
+            b"NBTC\0USD\0",
+            b"NBTC USD\0",
+            b"N\xff\0\0\0\0\0\0\0",
+        ] {
+            assert_eq!(Request::parse(message), Err(ParseError::InvalidAssetName));
+        }
+    }
+
+    #[test]
+    fn test_unknown_type() {
+        let err = Request::parse(&[b'i', 0, 0, 0, 0, 0, 0, 0, 0]).unwrap_err();
+        assert_eq!(err, ParseError::UnknownType(b'i'));
+        assert_eq!(err.to_string(), "unknown message type 0x69");
+    }
+}
@@ -1,16 +1,86 @@
+use std::sync::Arc;
+use std::time::Duration;
+
 use anyhow::Result;
 use tokio::net::TcpListener;
+use tokio::task;
+
+use protohackers_core::server::Server;
 
+use crate::assets::Assets;
 use crate::connection;
+use crate::store::DuplicatePolicy;
+use crate::wal::{Durability, FsyncPolicy};
+
+/// How often the logs of the assets are flushed to the disk under [`FsyncPolicy::Periodic`]
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Named assets kept at most by default
+pub const DEFAULT_MAX_ASSETS: usize = 1024;
+
+/// Tunables of the protocol, on top of those of the accept loop.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// What to do with a price inserted at the timestamp of another one of the same store
+    pub duplicates: DuplicatePolicy,
+    /// Named assets kept at most. Clients binding to another one are disconnected.
+    pub max_assets: usize,
+    /// Keep the prices of named assets across restarts. In memory only when `None`.
+    pub durability: Option<Durability>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            duplicates: DuplicatePolicy::default(),
+            max_assets: DEFAULT_MAX_ASSETS,
+            durability: None,
+        }
+    }
+}
 
 pub async fn run(listener: TcpListener) -> Result<()> {
-    loop {
-        let (stream, address) = listener.accept().await?;
+    serve(Server::new(listener), Options::default()).await
+}
 
-        info!("New connection from {}", address);
+/// Serve connections on an already configured `server` until it is shut down.
+pub async fn serve(server: Server, options: Options) -> Result<()> {
+    let idle_timeout = server.config().idle_timeout;
+    let fsync = options
+        .durability
+        .as_ref()
+        .map(|durability| durability.fsync);
+    let assets = Arc::new(match options.durability {
+        Some(durability) => Assets::open(options.duplicates, options.max_assets, durability)?,
+        None => Assets::new(options.duplicates, options.max_assets),
+    });
+
+    let syncing = (fsync == Some(FsyncPolicy::Periodic)).then(|| {
+        let assets = Arc::clone(&assets);
         tokio::spawn(async move {
-            connection::handle_connection(stream).await?;
-            Ok::<(), anyhow::Error>(())
-        });
+            let mut interval = tokio::time::interval(SYNC_INTERVAL);
+            loop {
+                interval.tick().await;
+                let assets = Arc::clone(&assets);
+                match task::spawn_blocking(move || assets.sync()).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => error!("Could not flush the logs of the assets: {err}"),
+                    Err(err) => error!("Flushing the logs of the assets failed: {err}"),
+                }
+            }
+        })
+    });
+
+    let connections = Arc::clone(&assets);
+    server
+        .run(move |stream, address| {
+            connection::handle_connection(stream, address, idle_timeout, Arc::clone(&connections))
+        })
+        .await;
+
+    if let Some(syncing) = syncing {
+        syncing.abort();
     }
+    task::spawn_blocking(move || assets.sync()).await??;
+    Ok(())
 }
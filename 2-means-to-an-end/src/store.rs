@@ -0,0 +1,648 @@
+//! Prices of an asset ordered by timestamp, with a summary of every subtree to answer range
+//! queries in logarithmic time.
+
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Display, Formatter};
+use std::hash::{BuildHasher, Hasher};
+
+/// What to do with a price inserted at the timestamp of another one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Refuse the new price, keeping the first one
+    Reject,
+    /// Replace the price already there
+    Overwrite,
+    /// Keep both prices, each counting in the queries
+    #[default]
+    KeepBoth,
+}
+
+/// A price was inserted at the timestamp of another one, under [`DuplicatePolicy::Reject`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateTimestamp(pub i32);
+
+impl Display for DuplicateTimestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "duplicate timestamp {}", self.0)
+    }
+}
+
+impl std::error::Error for DuplicateTimestamp {}
+
+/// Prices inserted in any order, as a treap keyed by timestamp.
+///
+/// Every node keeps a [`Summary`] of the prices in its subtree, so a range of timestamps is
+/// summarized from the subtrees along two paths from the root.
+#[derive(Debug)]
+pub struct PriceStore {
+    /// Nodes in insertion order, linked by index
+    nodes: Vec<Node>,
+    root: Option<usize>,
+    policy: DuplicatePolicy,
+    /// State of the xorshift generator of node priorities, randomly seeded so that clients
+    /// can't pick timestamps that degrade the treap into a list
+    seed: u32,
+}
+
+#[derive(Debug)]
+struct Node {
+    timestamp: i32,
+    price: i32,
+    /// Heap order of the treap, higher towards the root, which keeps it balanced
+    priority: u32,
+    left: Option<usize>,
+    right: Option<usize>,
+    /// Of the prices in the subtree rooted here
+    summary: Summary,
+}
+
+/// Aggregates of some prices, combined with `+`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    count: u64,
+    sum: i64,
+    /// Enough for 2^64 prices of up to 2^31 squared
+    sum_of_squares: i128,
+    min: i32,
+    max: i32,
+}
+
+impl Summary {
+    /// Of no prices at all.
+    pub const EMPTY: Summary = Summary {
+        count: 0,
+        sum: 0,
+        sum_of_squares: 0,
+        min: i32::MAX,
+        max: i32::MIN,
+    };
+
+    fn of(price: i32) -> Self {
+        Self {
+            count: 1,
+            sum: price.into(),
+            sum_of_squares: i128::from(price) * i128::from(price),
+            min: price,
+            max: price,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Rounded towards zero.
+    pub fn mean(&self) -> Option<i32> {
+        // The mean of `i32` prices fits in an `i32`
+        (self.count > 0).then(|| (self.sum / self.count as i64) as i32)
+    }
+
+    pub fn min(&self) -> Option<i32> {
+        (self.count > 0).then_some(self.min)
+    }
+
+    pub fn max(&self) -> Option<i32> {
+        (self.count > 0).then_some(self.max)
+    }
+
+    /// Population standard deviation.
+    pub fn std_dev(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        // n^2 times the variance, computed exactly
+        let count = i128::from(self.count);
+        let scaled = count * self.sum_of_squares - i128::from(self.sum) * i128::from(self.sum);
+        Some((scaled as f64).sqrt() / self.count as f64)
+    }
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl std::ops::Add for Summary {
+    type Output = Summary;
+
+    fn add(self, other: Summary) -> Summary {
+        Summary {
+            count: self.count + other.count,
+            sum: self.sum + other.sum,
+            sum_of_squares: self.sum_of_squares + other.sum_of_squares,
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+}
+
+impl Default for PriceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriceStore {
+    /// Empty store keeping the prices of duplicate timestamps.
+    pub fn new() -> Self {
+        Self::with_policy(DuplicatePolicy::default())
+    }
+
+    pub fn with_policy(policy: DuplicatePolicy) -> Self {
+        Self {
+            nodes: Vec::new(),
+            root: None,
+            policy,
+            seed: random_seed(),
+        }
+    }
+
+    /// Number of prices stored.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add the `price` at `timestamp`, following the duplicate policy if there is already one.
+    pub fn insert(&mut self, timestamp: i32, price: i32) -> Result<(), DuplicateTimestamp> {
+        if self.policy == DuplicatePolicy::Reject && self.contains(timestamp) {
+            return Err(DuplicateTimestamp(timestamp));
+        }
+        if self.policy == DuplicatePolicy::Overwrite && self.replace(self.root, timestamp, price) {
+            return Ok(());
+        }
+        self.push(timestamp, price);
+        Ok(())
+    }
+
+    /// Whether there is a price at `timestamp`.
+    pub fn contains(&self, timestamp: i32) -> bool {
+        let mut node = self.root;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            node = match timestamp.cmp(&current.timestamp) {
+                Ordering::Less => current.left,
+                Ordering::Greater => current.right,
+                Ordering::Equal => return true,
+            };
+        }
+        false
+    }
+
+    pub fn policy(&self) -> DuplicatePolicy {
+        self.policy
+    }
+
+    /// Every price with its timestamp, in the order they were inserted in. Inserting them in
+    /// this order in an empty store gives back the same prices.
+    pub fn prices(&self) -> impl Iterator<Item = (i32, i32)> + '_ {
+        self.nodes.iter().map(|node| (node.timestamp, node.price))
+    }
+
+    /// Mean of the prices with a timestamp between `start` and `end` inclusive, rounded
+    /// towards zero. 0 when there are none.
+    pub fn mean(&self, start: i32, end: i32) -> i32 {
+        self.summary(start, end).mean().unwrap_or(0)
+    }
+
+    /// Aggregates of the prices with a timestamp between `start` and `end` inclusive.
+    pub fn summary(&self, start: i32, end: i32) -> Summary {
+        let mut node = self.root;
+        // Down to the first node in the range, whose subtrees hold the rest of it
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            if current.timestamp < start {
+                node = current.right;
+            } else if current.timestamp > end {
+                node = current.left;
+            } else {
+                return self.summary_from(current.left, start)
+                    + Summary::of(current.price)
+                    + self.summary_up_to(current.right, end);
+            }
+        }
+        Summary::EMPTY
+    }
+
+    /// Median of the prices with a timestamp between `start` and `end` inclusive. With an even
+    /// number of prices, the mean of the two middle ones rounded towards zero.
+    ///
+    /// Unlike the other aggregates, takes time proportional to the number of prices in range.
+    pub fn median(&self, start: i32, end: i32) -> Option<i32> {
+        let mut prices = Vec::new();
+        self.collect(self.root, start, end, &mut prices);
+        if prices.is_empty() {
+            return None;
+        }
+        let (middle, odd) = (prices.len() / 2, prices.len() % 2 == 1);
+        let (lower, &mut upper, _) = prices.select_nth_unstable(middle);
+        if odd {
+            return Some(upper);
+        }
+        let lower = *lower
+            .iter()
+            .max()
+            .expect("an even number of prices is at least 2");
+        Some(((i64::from(lower) + i64::from(upper)) / 2) as i32)
+    }
+
+    /// Add a node for the price, keeping the tree balanced.
+    fn push(&mut self, timestamp: i32, price: i32) {
+        let priority = self.next_priority();
+        self.nodes.push(Node {
+            timestamp,
+            price,
+            priority,
+            left: None,
+            right: None,
+            summary: Summary::of(price),
+        });
+        let new = self.nodes.len() - 1;
+        self.root = Some(self.insert_under(self.root, new));
+    }
+
+    /// Replace the price at `timestamp` in the subtree rooted at `node`, returning whether
+    /// there was one. Only used when timestamps are unique.
+    fn replace(&mut self, node: Option<usize>, timestamp: i32, price: i32) -> bool {
+        let Some(index) = node else {
+            return false;
+        };
+        let current = &self.nodes[index];
+        let replaced = match timestamp.cmp(&current.timestamp) {
+            Ordering::Less => self.replace(current.left, timestamp, price),
+            Ordering::Greater => self.replace(current.right, timestamp, price),
+            Ordering::Equal => {
+                self.nodes[index].price = price;
+                true
+            }
+        };
+        if replaced {
+            self.update(index);
+        }
+        replaced
+    }
+
+    /// Insert the node `new` in the subtree rooted at `node`, returning the new root of it.
+    fn insert_under(&mut self, node: Option<usize>, new: usize) -> usize {
+        let Some(node) = node else {
+            return new;
+        };
+        let root = if self.nodes[new].timestamp < self.nodes[node].timestamp {
+            let left = self.insert_under(self.nodes[node].left, new);
+            self.nodes[node].left = Some(left);
+            if self.nodes[left].priority > self.nodes[node].priority {
+                self.rotate_right(node)
+            } else {
+                node
+            }
+        } else {
+            let right = self.insert_under(self.nodes[node].right, new);
+            self.nodes[node].right = Some(right);
+            if self.nodes[right].priority > self.nodes[node].priority {
+                self.rotate_left(node)
+            } else {
+                node
+            }
+        };
+        self.update(node);
+        self.update(root);
+        root
+    }
+
+    /// Lift the left child of `node` above it, returning that child.
+    fn rotate_right(&mut self, node: usize) -> usize {
+        let left = self.nodes[node]
+            .left
+            .expect("rotated node has a left child");
+        self.nodes[node].left = self.nodes[left].right;
+        self.nodes[left].right = Some(node);
+        left
+    }
+
+    /// Lift the right child of `node` above it, returning that child.
+    fn rotate_left(&mut self, node: usize) -> usize {
+        let right = self.nodes[node]
+            .right
+            .expect("rotated node has a right child");
+        self.nodes[node].right = self.nodes[right].left;
+        self.nodes[right].left = Some(node);
+        right
+    }
+
+    /// Recompute the summary of `node` from its children.
+    fn update(&mut self, node: usize) {
+        let left = self.subtree(self.nodes[node].left);
+        let right = self.subtree(self.nodes[node].right);
+        let node = &mut self.nodes[node];
+        node.summary = left + Summary::of(node.price) + right;
+    }
+
+    fn subtree(&self, node: Option<usize>) -> Summary {
+        node.map(|node| self.nodes[node].summary)
+            .unwrap_or_default()
+    }
+
+    /// Summary of the prices under `node` with a timestamp from `start`.
+    fn summary_from(&self, mut node: Option<usize>, start: i32) -> Summary {
+        let mut summary = Summary::EMPTY;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            if current.timestamp >= start {
+                summary = summary + Summary::of(current.price) + self.subtree(current.right);
+                node = current.left;
+            } else {
+                node = current.right;
+            }
+        }
+        summary
+    }
+
+    /// Summary of the prices under `node` with a timestamp up to `end`.
+    fn summary_up_to(&self, mut node: Option<usize>, end: i32) -> Summary {
+        let mut summary = Summary::EMPTY;
+        while let Some(index) = node {
+            let current = &self.nodes[index];
+            if current.timestamp <= end {
+                summary = summary + self.subtree(current.left) + Summary::of(current.price);
+                node = current.right;
+            } else {
+                node = current.left;
+            }
+        }
+        summary
+    }
+
+    /// Push the prices under `node` with a timestamp between `start` and `end` to `prices`.
+    fn collect(&self, node: Option<usize>, start: i32, end: i32, prices: &mut Vec<i32>) {
+        let Some(index) = node else {
+            return;
+        };
+        let current = &self.nodes[index];
+        if current.timestamp >= start {
+            self.collect(current.left, start, end, prices);
+        }
+        if (start..=end).contains(&current.timestamp) {
+            prices.push(current.price);
+        }
+        if current.timestamp <= end {
+            self.collect(current.right, start, end, prices);
+        }
+    }
+
+    fn next_priority(&mut self) -> u32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed
+    }
+}
+
+/// Seed of a store's priorities, different for every store. Never 0, which xorshift can't
+/// leave.
+fn random_seed() -> u32 {
+    // The keys of `RandomState` come from the OS, and change for each instance
+    let hash = RandomState::new().build_hasher().finish();
+    ((hash >> 32) as u32 ^ hash as u32).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// The mean computed by scanning every price.
+    fn naive_mean(prices: &[(i32, i32)], start: i32, end: i32) -> i32 {
+        let in_range: Vec<i64> = prices
+            .iter()
+            .filter(|(timestamp, _)| (start..=end).contains(timestamp))
+            .map(|&(_, price)| price.into())
+            .collect();
+        if in_range.is_empty() {
+            return 0;
+        }
+        (in_range.iter().sum::<i64>() / in_range.len() as i64) as i32
+    }
+
+    /// The prices kept by a plain list under `policy`.
+    fn naive_insert(prices: &[(i32, i32)], policy: DuplicatePolicy) -> Vec<(i32, i32)> {
+        let mut kept: Vec<(i32, i32)> = Vec::new();
+        for &(timestamp, price) in prices {
+            match kept.iter_mut().find(|(kept, _)| *kept == timestamp) {
+                Some(_) if policy == DuplicatePolicy::Reject => {}
+                Some(duplicate) if policy == DuplicatePolicy::Overwrite => duplicate.1 = price,
+                _ => kept.push((timestamp, price)),
+            }
+        }
+        kept
+    }
+
+    /// The median computed by sorting.
+    fn naive_median(prices: &[i32]) -> Option<i32> {
+        let mut prices = prices.to_vec();
+        prices.sort();
+        let middle = prices.len() / 2;
+        match prices.len() {
+            0 => None,
+            length if length % 2 == 1 => Some(prices[middle]),
+            _ => Some(((i64::from(prices[middle - 1]) + i64::from(prices[middle])) / 2) as i32),
+        }
+    }
+
+    /// The standard deviation computed with floats, in two passes.
+    fn naive_std_dev(prices: &[i32]) -> Option<f64> {
+        if prices.is_empty() {
+            return None;
+        }
+        let count = prices.len() as f64;
+        let mean = prices.iter().map(|&price| f64::from(price)).sum::<f64>() / count;
+        let variance = prices
+            .iter()
+            .map(|&price| (f64::from(price) - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        Some(variance.sqrt())
+    }
+
+    fn depth(store: &PriceStore, node: Option<usize>) -> usize {
+        node.map_or(0, |node| {
+            let node = &store.nodes[node];
+            1 + depth(store, node.left).max(depth(store, node.right))
+        })
+    }
+
+    #[test]
+    fn test_mean() {
+        let mut store = PriceStore::new();
+        assert_eq!(store.mean(i32::MIN, i32::MAX), 0);
+
+        for (timestamp, price) in [(12345, 101), (12347, 100), (12346, 102), (40960, 5)] {
+            store.insert(timestamp, price).unwrap();
+        }
+        assert_eq!(store.len(), 4);
+        assert_eq!(store.mean(12288, 16384), 101);
+        assert_eq!(store.mean(12346, 12346), 102);
+        assert_eq!(store.mean(12346, 40960), 69);
+        assert_eq!(store.mean(16384, 12288), 0);
+        assert_eq!(store.mean(0, 100), 0);
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        let mut store = PriceStore::new();
+        store.insert(i32::MIN, i32::MIN).unwrap();
+        store.insert(i32::MAX, i32::MIN).unwrap();
+        store.insert(0, i32::MIN).unwrap();
+        assert_eq!(store.mean(i32::MIN, i32::MAX), i32::MIN);
+        store.insert(1, i32::MAX).unwrap();
+        assert_eq!(store.mean(0, 1), 0);
+        assert_eq!(store.mean(i32::MAX, i32::MAX), i32::MIN);
+    }
+
+    #[test]
+    fn test_balanced() {
+        // Sorted insertions are the worst case of an unbalanced tree
+        let mut store = PriceStore::new();
+        for timestamp in 0..100_000 {
+            store.insert(timestamp, 1).unwrap();
+        }
+        assert!(depth(&store, store.root) < 60);
+        assert_eq!(store.mean(0, 99_999), 1);
+    }
+
+    #[test]
+    fn test_unpredictable_priorities() {
+        // Otherwise the timestamps degrading one store would degrade them all
+        assert_ne!(PriceStore::new().seed, PriceStore::new().seed);
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let mut store = PriceStore::with_policy(DuplicatePolicy::KeepBoth);
+        store.insert(10, 100).unwrap();
+        store.insert(10, 200).unwrap();
+        assert_eq!((store.len(), store.mean(10, 10)), (2, 150));
+
+        let mut store = PriceStore::with_policy(DuplicatePolicy::Overwrite);
+        store.insert(10, 100).unwrap();
+        store.insert(20, 100).unwrap();
+        store.insert(10, 200).unwrap();
+        assert_eq!((store.len(), store.mean(10, 10)), (2, 200));
+        assert_eq!(store.mean(0, 20), 150);
+
+        let mut store = PriceStore::with_policy(DuplicatePolicy::Reject);
+        store.insert(10, 100).unwrap();
+        assert_eq!(store.insert(10, 200), Err(DuplicateTimestamp(10)));
+        assert_eq!((store.len(), store.mean(10, 10)), (1, 100));
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let mut store = PriceStore::new();
+        for (timestamp, price) in [
+            (8, 9),
+            (1, 2),
+            (3, 4),
+            (2, 4),
+            (4, 4),
+            (7, 7),
+            (5, 5),
+            (6, 5),
+        ] {
+            store.insert(timestamp, price).unwrap();
+        }
+        let summary = store.summary(1, 8);
+        assert_eq!(summary.count(), 8);
+        assert_eq!(summary.mean(), Some(5));
+        assert_eq!(summary.min(), Some(2));
+        assert_eq!(summary.max(), Some(9));
+        assert_eq!(summary.std_dev(), Some(2.0));
+        assert_eq!(store.median(1, 8), Some(4));
+        assert_eq!(store.median(2, 8), Some(5));
+        assert_eq!(store.median(7, 8), Some(8));
+
+        let summary = store.summary(9, 100);
+        assert_eq!(summary, Summary::EMPTY);
+        assert_eq!(
+            (summary.mean(), summary.min(), summary.max()),
+            (None, None, None)
+        );
+        assert_eq!(summary.std_dev(), None);
+        assert_eq!(store.median(9, 100), None);
+        assert_eq!(store.summary(8, 1), Summary::EMPTY);
+        assert_eq!(store.median(8, 1), None);
+    }
+
+    #[test]
+    fn test_extreme_aggregates() {
+        let mut store = PriceStore::new();
+        store.insert(0, i32::MIN).unwrap();
+        store.insert(1, i32::MAX).unwrap();
+        let summary = store.summary(0, 1);
+        assert_eq!(summary.std_dev(), Some(f64::from(i32::MAX) + 0.5));
+        assert_eq!(store.median(0, 1), Some(0));
+    }
+
+    proptest! {
+        #[test]
+        fn test_matches_naive(
+            // A small range of timestamps gives duplicates and hits
+            prices in prop::collection::vec((-50..50, any::<i32>()), 0..200),
+            queries in prop::collection::vec((-60..60, -60..60), 1..20),
+            policy in prop_oneof![
+                Just(DuplicatePolicy::Reject),
+                Just(DuplicatePolicy::Overwrite),
+                Just(DuplicatePolicy::KeepBoth),
+            ],
+        ) {
+            let mut store = PriceStore::with_policy(policy);
+            for &(timestamp, price) in &prices {
+                let _ = store.insert(timestamp, price);
+            }
+            let kept = naive_insert(&prices, policy);
+            prop_assert_eq!(store.len(), kept.len());
+            for (start, end) in queries {
+                prop_assert_eq!(store.mean(start, end), naive_mean(&kept, start, end));
+
+                let in_range: Vec<i32> = kept
+                    .iter()
+                    .filter(|(timestamp, _)| (start..=end).contains(timestamp))
+                    .map(|&(_, price)| price)
+                    .collect();
+                let summary = store.summary(start, end);
+                prop_assert_eq!(summary.count(), in_range.len() as u64);
+                prop_assert_eq!(summary.min(), in_range.iter().min().copied());
+                prop_assert_eq!(summary.max(), in_range.iter().max().copied());
+                prop_assert_eq!(store.median(start, end), naive_median(&in_range));
+                match (summary.std_dev(), naive_std_dev(&in_range)) {
+                    (Some(std_dev), Some(naive)) => {
+                        prop_assert!((std_dev - naive).abs() <= 1e-6 * naive.max(1.0));
+                    }
+                    (std_dev, naive) => prop_assert_eq!(std_dev, naive),
+                }
+            }
+        }
+
+        #[test]
+        fn test_matches_naive_any_timestamp(
+            prices in prop::collection::vec(any::<(i32, i32)>(), 0..100),
+            start in any::<i32>(),
+            end in any::<i32>(),
+        ) {
+            let mut store = PriceStore::new();
+            for &(timestamp, price) in &prices {
+                store.insert(timestamp, price).unwrap();
+            }
+            prop_assert_eq!(store.mean(start, end), naive_mean(&prices, start, end));
+            prop_assert_eq!(
+                store.mean(i32::MIN, i32::MAX),
+                naive_mean(&prices, i32::MIN, i32::MAX)
+            );
+        }
+    }
+}
@@ -0,0 +1,565 @@
+//! Write-ahead log of the prices inserted in named assets, compacted into snapshots.
+//!
+//! An asset has a `<name>.<generation>.snapshot` and a `<name>.<generation>.log` file in the
+//! data directory. The snapshot holds the prices inserted before the generation started, and
+//! the log those inserted since, both as records of a big-endian timestamp and price.
+//!
+//! Compacting writes the snapshot of the next generation under a temporary name, renames it
+//! into place, then starts the log of that generation and removes the files of the previous
+//! one. Wherever a crash happens, recovery replays the files of a single generation: the
+//! latest with a snapshot.
+
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::store::{DuplicatePolicy, PriceStore};
+
+/// Size of a record, in the snapshots and the logs
+pub const RECORD_SIZE: usize = 8;
+
+/// Log records after which an asset is compacted into a snapshot by default
+pub const DEFAULT_SNAPSHOT_EVERY: u64 = 100_000;
+
+/// When the log of an asset is flushed to the disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum FsyncPolicy {
+    /// After every insert, which is only answered for once on the disk
+    Always,
+    /// Every second, losing at most the inserts of the last second on a crash
+    #[default]
+    Periodic,
+    /// Left to the operating system
+    Never,
+}
+
+/// Where and how the prices of named assets are kept across restarts.
+#[derive(Clone, Debug)]
+pub struct Durability {
+    pub data_dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    /// Log records after which an asset is compacted into a snapshot
+    pub snapshot_every: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FileKind {
+    Snapshot,
+    Log,
+    /// A snapshot still being written
+    Temporary,
+}
+
+impl FileKind {
+    fn extension(self) -> &'static str {
+        match self {
+            FileKind::Snapshot => "snapshot",
+            FileKind::Log => "log",
+            FileKind::Temporary => "snapshot.tmp",
+        }
+    }
+}
+
+/// The log an asset is appended to.
+#[derive(Debug)]
+pub struct Wal {
+    durability: Durability,
+    name: String,
+    generation: u64,
+    log: File,
+    /// In the log of the current generation
+    records: u64,
+    /// Whether some records may not be on the disk yet
+    dirty: bool,
+    /// Whether a failed append may have left part of a record at the end of the log
+    torn: bool,
+}
+
+impl Wal {
+    /// Start the log of an asset without any files yet.
+    pub fn create(durability: &Durability, name: &str) -> io::Result<Self> {
+        let log = File::create(file_path(&durability.data_dir, name, 0, FileKind::Log))?;
+        sync_dir(&durability.data_dir)?;
+        Ok(Self {
+            durability: durability.clone(),
+            name: name.to_string(),
+            generation: 0,
+            log,
+            records: 0,
+            dirty: false,
+            torn: false,
+        })
+    }
+
+    /// Replay the files of the asset `name`, giving back its prices and its log to append
+    /// to. A record cut short at the end of the log, by a crash while it was being written,
+    /// is dropped.
+    pub fn recover(
+        durability: &Durability,
+        name: &str,
+        duplicates: DuplicatePolicy,
+    ) -> anyhow::Result<(Self, PriceStore)> {
+        let dir = &durability.data_dir;
+        let files: Vec<(u64, FileKind)> = list_files(dir)?
+            .into_iter()
+            .filter(|(file_name, _, _)| file_name == name)
+            .map(|(_, generation, kind)| (generation, kind))
+            .collect();
+        let generation = files
+            .iter()
+            .filter(|(_, kind)| *kind == FileKind::Snapshot)
+            .map(|&(generation, _)| generation)
+            .max()
+            .unwrap_or(0);
+
+        let mut prices = PriceStore::with_policy(duplicates);
+        let snapshot_path = file_path(dir, name, generation, FileKind::Snapshot);
+        if files.contains(&(generation, FileKind::Snapshot)) {
+            let snapshot = fs::read(&snapshot_path)
+                .with_context(|| format!("Could not read {}", snapshot_path.display()))?;
+            if snapshot.len() % RECORD_SIZE != 0 {
+                bail!("Corrupt snapshot {}", snapshot_path.display());
+            }
+            replay(&mut prices, &snapshot);
+        }
+
+        let log_path = file_path(dir, name, generation, FileKind::Log);
+        let mut log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&log_path)
+            .with_context(|| format!("Could not open {}", log_path.display()))?;
+        let mut records = Vec::new();
+        log.read_to_end(&mut records)?;
+        let complete = records.len() - records.len() % RECORD_SIZE;
+        if complete < records.len() {
+            warn!(
+                "Dropping a partial record at the end of {}. length={}",
+                log_path.display(),
+                records.len() - complete
+            );
+            log.set_len(complete as u64)?;
+            log.sync_all()?;
+        }
+        replay(&mut prices, &records[..complete]);
+        log.seek(SeekFrom::Start(complete as u64))?;
+
+        // Left by a compaction, complete or not
+        for (stale, kind) in files {
+            if stale != generation || kind == FileKind::Temporary {
+                fs::remove_file(file_path(dir, name, stale, kind))?;
+            }
+        }
+        sync_dir(dir)?;
+
+        let wal = Self {
+            durability: durability.clone(),
+            name: name.to_string(),
+            generation,
+            log,
+            records: (complete / RECORD_SIZE) as u64,
+            dirty: false,
+            torn: false,
+        };
+        Ok((wal, prices))
+    }
+
+    /// Log the insert of `price` at `timestamp`, before it is applied.
+    ///
+    /// On failure, nothing of the record is left in the log, so it must not be applied.
+    pub fn append(&mut self, timestamp: i32, price: i32) -> io::Result<()> {
+        if self.torn {
+            self.truncate()?;
+        }
+        let mut written = self.log.write_all(&record(timestamp, price));
+        if written.is_ok() && self.durability.fsync == FsyncPolicy::Always {
+            written = self.log.sync_data();
+        }
+        if let Err(err) = written {
+            // The records appended next must not follow part of this one
+            self.torn = true;
+            if let Err(truncate_err) = self.truncate() {
+                warn!(
+                    "Could not truncate the log of {}: {truncate_err}",
+                    self.name
+                );
+            }
+            return Err(err);
+        }
+        self.records += 1;
+        if self.durability.fsync != FsyncPolicy::Always {
+            self.dirty = true;
+        }
+        Ok(())
+    }
+
+    /// Cut the log back to the end of its last whole record.
+    fn truncate(&mut self) -> io::Result<()> {
+        let end = self.records * RECORD_SIZE as u64;
+        self.log.set_len(end)?;
+        self.log.seek(SeekFrom::Start(end))?;
+        self.torn = false;
+        Ok(())
+    }
+
+    /// Whether the log grew long enough to be compacted.
+    pub fn needs_snapshot(&self) -> bool {
+        self.records >= self.durability.snapshot_every
+    }
+
+    /// Compact every one of `prices`, as `(timestamp, price)`, into the snapshot of the next
+    /// generation, and start its empty log.
+    ///
+    /// On failure, the current log is kept, so it can still be appended to and compacted later.
+    pub fn snapshot(&mut self, prices: impl IntoIterator<Item = (i32, i32)>) -> io::Result<()> {
+        let dir = &self.durability.data_dir;
+        let next = self.generation + 1;
+        let temporary = file_path(dir, &self.name, next, FileKind::Temporary);
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        for (timestamp, price) in prices {
+            writer.write_all(&record(timestamp, price))?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        let snapshot = file_path(dir, &self.name, next, FileKind::Snapshot);
+        fs::rename(&temporary, &snapshot)?;
+        let log_path = file_path(dir, &self.name, next, FileKind::Log);
+        let log = File::create(&log_path).and_then(|log| sync_dir(dir).map(|()| log));
+        let log = match log {
+            Ok(log) => log,
+            Err(err) => {
+                // Otherwise the snapshot would be replayed instead of the records appended to
+                // the current log from now on
+                let _ = fs::remove_file(&log_path);
+                fs::remove_file(&snapshot)?;
+                return Err(err);
+            }
+        };
+
+        let previous = self.generation;
+        self.generation = next;
+        self.log = log;
+        self.records = 0;
+        self.dirty = false;
+        self.torn = false;
+        // Left for the recovery to remove if this fails
+        for kind in [FileKind::Log, FileKind::Snapshot] {
+            match fs::remove_file(file_path(dir, &self.name, previous, kind)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the records logged since the last time to the disk.
+    pub fn sync(&mut self) -> io::Result<()> {
+        if self.dirty {
+            self.log.sync_data()?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+/// Names of the assets with files in `dir`.
+pub fn asset_names(dir: &Path) -> io::Result<BTreeSet<String>> {
+    Ok(list_files(dir)?
+        .into_iter()
+        .map(|(name, _, _)| name)
+        .collect())
+}
+
+/// Asset name, generation and kind of the files of `dir`, skipping the others.
+fn list_files(dir: &Path) -> io::Result<Vec<(String, u64, FileKind)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file_name = entry?.file_name();
+        if let Some(file) = file_name.to_str().and_then(parse_file_name) {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+fn parse_file_name(file_name: &str) -> Option<(String, u64, FileKind)> {
+    let (rest, kind) = [FileKind::Temporary, FileKind::Snapshot, FileKind::Log]
+        .into_iter()
+        .find_map(|kind| {
+            let rest = file_name.strip_suffix(kind.extension())?;
+            Some((rest.strip_suffix('.')?, kind))
+        })?;
+    let (name, generation) = rest.rsplit_once('.')?;
+    if name.is_empty() || !generation.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((name.to_string(), generation.parse().ok()?, kind))
+}
+
+fn file_path(dir: &Path, name: &str, generation: u64, kind: FileKind) -> PathBuf {
+    dir.join(format!("{name}.{generation}.{}", kind.extension()))
+}
+
+fn record(timestamp: i32, price: i32) -> [u8; RECORD_SIZE] {
+    let mut record = [0; RECORD_SIZE];
+    record[..4].copy_from_slice(&timestamp.to_be_bytes());
+    record[4..].copy_from_slice(&price.to_be_bytes());
+    record
+}
+
+fn replay(prices: &mut PriceStore, records: &[u8]) {
+    for record in records.chunks_exact(RECORD_SIZE) {
+        let timestamp = i32::from_be_bytes(record[..4].try_into().unwrap());
+        let price = i32::from_be_bytes(record[4..].try_into().unwrap());
+        // Rejected duplicates were never logged
+        let _ = prices.insert(timestamp, price);
+    }
+}
+
+/// Make the creation, renaming and removal of files in `dir` durable.
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn durability(dir: &TempDir, snapshot_every: u64) -> Durability {
+        Durability {
+            data_dir: dir.path().to_path_buf(),
+            fsync: FsyncPolicy::Always,
+            snapshot_every,
+        }
+    }
+
+    /// Log and apply like a shared asset does.
+    fn insert(wal: &mut Wal, prices: &mut PriceStore, timestamp: i32, price: i32) {
+        wal.append(timestamp, price).unwrap();
+        prices.insert(timestamp, price).unwrap();
+        if wal.needs_snapshot() {
+            wal.snapshot(prices.prices()).unwrap();
+        }
+    }
+
+    fn recover(durability: &Durability) -> (Wal, PriceStore) {
+        Wal::recover(durability, "BTC", DuplicatePolicy::KeepBoth).unwrap()
+    }
+
+    fn file_names(dir: &TempDir) -> BTreeSet<String> {
+        fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(
+            parse_file_name("BTC.3.log"),
+            Some(("BTC".to_string(), 3, FileKind::Log))
+        );
+        assert_eq!(
+            parse_file_name("a.1.0.snapshot"),
+            Some(("a.1".to_string(), 0, FileKind::Snapshot))
+        );
+        assert_eq!(
+            parse_file_name("...12.snapshot.tmp"),
+            Some(("..".to_string(), 12, FileKind::Temporary))
+        );
+        for file_name in ["BTC.log", "BTC.x.log", ".0.log", "BTC.0.logs", "BTC.-1.log"] {
+            assert_eq!(parse_file_name(file_name), None, "{file_name}");
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 1000);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..10 {
+            insert(&mut wal, &mut prices, timestamp, timestamp * 10);
+        }
+        drop(wal);
+
+        let (mut wal, mut recovered) = recover(&durability);
+        assert_eq!(
+            recovered.prices().collect::<Vec<_>>(),
+            prices.prices().collect::<Vec<_>>()
+        );
+        // Appended after the recovered records
+        insert(&mut wal, &mut recovered, 10, 100);
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 11);
+        assert_eq!(recovered.mean(0, 10), 50);
+        assert_eq!(
+            asset_names(dir.path()).unwrap(),
+            BTreeSet::from(["BTC".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_truncated_record() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 1000);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..5 {
+            insert(&mut wal, &mut prices, timestamp, 100);
+        }
+        drop(wal);
+
+        // Every way the last record could have been cut short
+        let log_path = dir.path().join("BTC.0.log");
+        let whole = fs::read(&log_path).unwrap();
+        for cut in 1..RECORD_SIZE {
+            fs::write(&log_path, &whole[..whole.len() - cut]).unwrap();
+            let (mut wal, mut recovered) = recover(&durability);
+            assert_eq!(recovered.len(), 4, "cut {cut}");
+            assert_eq!(
+                fs::metadata(&log_path).unwrap().len(),
+                4 * RECORD_SIZE as u64
+            );
+
+            // Appended at a record boundary
+            insert(&mut wal, &mut recovered, 4, 200);
+            drop(wal);
+            let (_, recovered) = recover(&durability);
+            assert_eq!(recovered.len(), 5);
+            assert_eq!(recovered.mean(4, 4), 200);
+        }
+    }
+
+    #[test]
+    fn test_failed_append() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 1000);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..3 {
+            insert(&mut wal, &mut prices, timestamp, 100);
+        }
+        // Left by a write that failed halfway through a record
+        wal.log.write_all(&record(3, 300)[..5]).unwrap();
+        wal.torn = true;
+
+        insert(&mut wal, &mut prices, 4, 400);
+        drop(wal);
+        let (_, recovered) = recover(&durability);
+        assert_eq!(
+            recovered.prices().collect::<Vec<_>>(),
+            [(0, 100), (1, 100), (2, 100), (4, 400)]
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 10);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..25 {
+            insert(&mut wal, &mut prices, timestamp, 100);
+        }
+        drop(wal);
+
+        assert_eq!(
+            file_names(&dir),
+            BTreeSet::from(["BTC.2.log".to_string(), "BTC.2.snapshot".to_string()])
+        );
+        assert_eq!(
+            fs::metadata(dir.path().join("BTC.2.log")).unwrap().len(),
+            40
+        );
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 25);
+    }
+
+    #[test]
+    fn test_failed_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 1000);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..3 {
+            insert(&mut wal, &mut prices, timestamp, 100);
+        }
+
+        // The log of the next generation can't be created, after its snapshot was written
+        fs::create_dir(dir.path().join("BTC.1.log")).unwrap();
+        assert!(wal.snapshot(prices.prices()).is_err());
+        assert!(!dir.path().join("BTC.1.snapshot").exists());
+
+        // Still appended to the current log, and compacted once possible
+        insert(&mut wal, &mut prices, 3, 100);
+        fs::remove_dir(dir.path().join("BTC.1.log")).unwrap();
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 4);
+        wal.snapshot(prices.prices()).unwrap();
+        drop(wal);
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 4);
+        assert_eq!(
+            file_names(&dir),
+            BTreeSet::from(["BTC.1.log".to_string(), "BTC.1.snapshot".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_snapshot_keeps_overwrites() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 3);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::with_policy(DuplicatePolicy::Overwrite);
+        for (timestamp, price) in [(1, 10), (2, 20), (1, 30), (3, 40), (2, 50)] {
+            insert(&mut wal, &mut prices, timestamp, price);
+        }
+        drop(wal);
+
+        let (_, recovered) = Wal::recover(&durability, "BTC", DuplicatePolicy::Overwrite).unwrap();
+        assert_eq!(
+            recovered.prices().collect::<Vec<_>>(),
+            [(1, 30), (2, 50), (3, 40)]
+        );
+    }
+
+    #[test]
+    fn test_crash_during_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let durability = durability(&dir, 1000);
+        let mut wal = Wal::create(&durability, "BTC").unwrap();
+        let mut prices = PriceStore::new();
+        for timestamp in 0..5 {
+            insert(&mut wal, &mut prices, timestamp, 100);
+        }
+        let log = fs::read(dir.path().join("BTC.0.log")).unwrap();
+
+        // Crashed before renaming the snapshot: the previous generation is replayed
+        fs::write(dir.path().join("BTC.1.snapshot.tmp"), &log[..12]).unwrap();
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 5);
+        assert_eq!(file_names(&dir), BTreeSet::from(["BTC.0.log".to_string()]));
+
+        // Crashed after renaming it, before removing the previous log: it is not replayed
+        // on top of the snapshot
+        wal.snapshot(prices.prices()).unwrap();
+        fs::write(dir.path().join("BTC.0.log"), &log).unwrap();
+        fs::remove_file(dir.path().join("BTC.1.log")).unwrap();
+        let (_, recovered) = recover(&durability);
+        assert_eq!(recovered.len(), 5);
+        assert_eq!(
+            file_names(&dir),
+            BTreeSet::from(["BTC.1.log".to_string(), "BTC.1.snapshot".to_string()])
+        );
+    }
+}
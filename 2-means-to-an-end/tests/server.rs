@@ -4,6 +4,9 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 
 use means_to_an_end::server;
+use means_to_an_end::store::DuplicatePolicy;
+use means_to_an_end::wal::{Durability, FsyncPolicy};
+use protohackers_core::server::Server;
 
 async fn start_server() -> SocketAddr {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
@@ -33,3 +36,229 @@ async fn test_server() {
 
     assert_eq!(response, [0x00, 0x00, 0x00, 0x65]);
 }
+
+#[tokio::test]
+async fn test_unknown_message_type() {
+    let server = start_server().await;
+    let mut connection = TcpStream::connect(server).await.unwrap();
+
+    let input = [
+        0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65, // I 12345 101
+        0x58, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x40, 0x00, // X
+    ];
+    connection.write_all(&input).await.unwrap();
+
+    // Disconnected without an answer
+    let mut response = Vec::new();
+    connection.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+
+    // The server keeps serving other clients
+    let mut connection = TcpStream::connect(server).await.unwrap();
+    let input = [
+        0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x65, // I 12345 101
+        0x51, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x40, 0x00, // Q 12288 16384
+    ];
+    connection.write_all(&input).await.unwrap();
+    let mut response = [0u8; 4];
+    connection.read_exact(&mut response).await.unwrap();
+    assert_eq!(i32::from_be_bytes(response), 101);
+}
+
+#[tokio::test]
+async fn test_duplicate_policies() {
+    let input = [
+        0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0x64, // I 12345 100
+        0x49, 0x00, 0x00, 0x30, 0x39, 0x00, 0x00, 0x00, 0xc8, // I 12345 200
+        0x51, 0x00, 0x00, 0x30, 0x00, 0x00, 0x00, 0x40, 0x00, // Q 12288 16384
+    ];
+    for (duplicates, expected) in [
+        (DuplicatePolicy::KeepBoth, Some(150)),
+        (DuplicatePolicy::Overwrite, Some(200)),
+        // Disconnected on the second insert
+        (DuplicatePolicy::Reject, None),
+    ] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = server::Options {
+            duplicates,
+            ..Default::default()
+        };
+        tokio::spawn(server::serve(Server::new(listener), options));
+
+        let mut connection = TcpStream::connect(addr).await.unwrap();
+        connection.write_all(&input).await.unwrap();
+        let mut response = Vec::new();
+        connection.shutdown().await.unwrap();
+        // A rejected client is disconnected with its query unread, which may reset the connection
+        let _ = connection.read_to_end(&mut response).await;
+        let mean =
+            (!response.is_empty()).then(|| i32::from_be_bytes(response[..].try_into().unwrap()));
+        assert_eq!(mean, expected, "{duplicates:?}");
+    }
+}
+
+#[tokio::test]
+async fn test_aggregates() {
+    let server = start_server().await;
+    let mut connection = TcpStream::connect(server).await.unwrap();
+
+    let mut input = Vec::new();
+    for (timestamp, price) in [
+        (1, 2),
+        (2, 4),
+        (3, 4),
+        (4, 4),
+        (5, 5),
+        (6, 5),
+        (7, 7),
+        (8, 9),
+    ] {
+        input.push(b'I');
+        input.extend(i32::to_be_bytes(timestamp));
+        input.extend(i32::to_be_bytes(price));
+    }
+    for message_type in *b"QLHCMS" {
+        input.push(message_type);
+        input.extend(i32::to_be_bytes(1));
+        input.extend(i32::to_be_bytes(8));
+    }
+    // No prices in range
+    input.extend([b'L', 0, 0, 0, 100, 0, 0, 0, 200]);
+    connection.write_all(&input).await.unwrap();
+
+    let mut answers = Vec::new();
+    for _ in 0..7 {
+        answers.push(connection.read_i32().await.unwrap());
+    }
+    // Mean, min, max, count, median and standard deviation
+    assert_eq!(answers, [5, 2, 9, 8, 4, 2, 0]);
+}
+
+fn message(message_type: u8, first: i32, second: i32) -> Vec<u8> {
+    let mut message = vec![message_type];
+    message.extend(first.to_be_bytes());
+    message.extend(second.to_be_bytes());
+    message
+}
+
+#[tokio::test]
+async fn test_shared_asset() {
+    let server = start_server().await;
+    let bind = b"NBTC\0\0\0\0\0";
+
+    let mut writer = TcpStream::connect(server).await.unwrap();
+    writer.write_all(bind).await.unwrap();
+    writer.write_all(&message(b'I', 1, 100)).await.unwrap();
+    writer.write_all(&message(b'I', 2, 200)).await.unwrap();
+    // Answered once the inserts are done
+    writer.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(writer.read_i32().await.unwrap(), 2);
+
+    let mut reader = TcpStream::connect(server).await.unwrap();
+    reader.write_all(bind).await.unwrap();
+    reader.write_all(&message(b'Q', 0, 10)).await.unwrap();
+    assert_eq!(reader.read_i32().await.unwrap(), 150);
+    reader.write_all(&message(b'I', 3, 300)).await.unwrap();
+    reader.write_all(&message(b'Q', 0, 10)).await.unwrap();
+    assert_eq!(reader.read_i32().await.unwrap(), 200);
+
+    // Still there once the first connection is gone
+    drop(writer);
+    let mut other = TcpStream::connect(server).await.unwrap();
+    other.write_all(b"NBTC\0\0\0\0\0").await.unwrap();
+    other.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(other.read_i32().await.unwrap(), 3);
+
+    // Connections not bound to it keep their own prices
+    let mut private = TcpStream::connect(server).await.unwrap();
+    private.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(private.read_i32().await.unwrap(), 0);
+    let mut other = TcpStream::connect(server).await.unwrap();
+    other.write_all(b"NETH\0\0\0\0\0").await.unwrap();
+    other.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(other.read_i32().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_bind_after_other_messages() {
+    let server = start_server().await;
+    let mut connection = TcpStream::connect(server).await.unwrap();
+
+    connection.write_all(&message(b'I', 1, 100)).await.unwrap();
+    connection.write_all(b"NBTC\0\0\0\0\0").await.unwrap();
+
+    let mut response = Vec::new();
+    connection.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+}
+
+#[tokio::test]
+async fn test_max_assets() {
+    let options = server::Options {
+        max_assets: 1,
+        ..Default::default()
+    };
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server::serve(Server::new(listener), options));
+
+    let mut btc = TcpStream::connect(addr).await.unwrap();
+    btc.write_all(b"NBTC\0\0\0\0\0").await.unwrap();
+    btc.write_all(&message(b'I', 1, 100)).await.unwrap();
+
+    // Refused, as binding to it would keep one more asset
+    let mut eth = TcpStream::connect(addr).await.unwrap();
+    eth.write_all(b"NETH\0\0\0\0\0").await.unwrap();
+    let mut response = Vec::new();
+    eth.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+
+    let mut other = TcpStream::connect(addr).await.unwrap();
+    other.write_all(b"NBTC\0\0\0\0\0").await.unwrap();
+    other.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(other.read_i32().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_durable_asset_survives_restart() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let options = server::Options {
+        durability: Some(Durability {
+            data_dir: dir.path().to_path_buf(),
+            fsync: FsyncPolicy::Periodic,
+            snapshot_every: 3,
+        }),
+        ..Default::default()
+    };
+    let bind = b"NBTC\0\0\0\0\0";
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::new(listener);
+    let shutdown = server.shutdown_handle();
+    let serving = tokio::spawn(server::serve(server, options.clone()));
+
+    let mut connection = TcpStream::connect(addr).await.unwrap();
+    connection.write_all(bind).await.unwrap();
+    for (timestamp, price) in [(1, 100), (2, 200), (3, 300), (4, 400), (5, 500)] {
+        connection
+            .write_all(&message(b'I', timestamp, price))
+            .await
+            .unwrap();
+    }
+    connection.write_all(&message(b'C', 0, 10)).await.unwrap();
+    assert_eq!(connection.read_i32().await.unwrap(), 5);
+    drop(connection);
+    shutdown.trigger();
+    serving.await.unwrap().unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(server::serve(Server::new(listener), options));
+
+    let mut connection = TcpStream::connect(addr).await.unwrap();
+    connection.write_all(bind).await.unwrap();
+    connection.write_all(&message(b'Q', 0, 10)).await.unwrap();
+    assert_eq!(connection.read_i32().await.unwrap(), 300);
+}
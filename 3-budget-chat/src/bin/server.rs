@@ -1,15 +1,31 @@
 use anyhow::Result;
+use clap::Parser;
 use log::info;
 
 use budget_chat::server::Server;
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::{cli, logging, metrics};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(flatten)]
+    server: ServerArgs,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
-    let address = "0.0.0.0:9003";
-    let server = Server::new(address).await?;
+    let cli: Cli = cli::parse(9003);
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
+
+    let server = Server::new(cli.server.bind_address())
+        .await?
+        .with_config(cli.server.server_config());
+    server.shutdown_handle().trigger_on_signal();
 
-    info!("Start Budget Chat at {address}");
+    info!("Start Budget Chat at {}", server.local_addr()?);
     server.run().await;
     Ok(())
 }
@@ -1,18 +1,29 @@
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use anyhow::{bail, Result};
+use protohackers_core::metrics::{Metered, FRAMES_PARSED};
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
+use protohackers_core::timeout::timeout;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
+use tokio::time::{sleep, Instant};
 
 use crate::server::ChatEvent;
 
 pub struct Connection {
-    socket_rx: BufReader<OwnedReadHalf>,
-    socket_tx: OwnedWriteHalf,
+    socket_rx: BufReader<Metered<OwnedReadHalf>>,
+    socket_tx: Metered<OwnedWriteHalf>,
     joined_users: Arc<RwLock<Vec<String>>>,
     chat_tx_channel: broadcast::Sender<ChatEvent>,
+    shutdown: Shutdown,
+    /// Time to pick a username
+    handshake_timeout: Option<Duration>,
+    /// Users that don't send any message for this long leave the room
+    idle_timeout: Option<Duration>,
 }
 
 impl Connection {
@@ -20,29 +31,87 @@ impl Connection {
         socket: TcpStream,
         joined_users: Arc<RwLock<Vec<String>>>,
         chat_tx_channel: broadcast::Sender<ChatEvent>,
+        shutdown: Shutdown,
+        config: &Config,
     ) -> Self {
         let (socket_rx, socket_tx) = socket.into_split();
         Self {
-            socket_rx: BufReader::new(socket_rx),
-            socket_tx,
+            socket_rx: BufReader::new(Metered::new(socket_rx)),
+            socket_tx: Metered::new(socket_tx),
             joined_users,
             chat_tx_channel,
+            shutdown,
+            handshake_timeout: config.handshake_timeout,
+            idle_timeout: config.idle_timeout,
         }
     }
 
     pub async fn handle(mut self) -> Result<()> {
-        let self_username = self.user_join().await?;
+        let shutdown = self.shutdown.clone();
+        let handshake_timeout = self.handshake_timeout;
+        let self_username = tokio::select! {
+            username = timeout(handshake_timeout, self.user_join()) => match username {
+                Ok(username) => username?,
+                Err(_) => {
+                    self.socket_tx.write_all(b"* Timed out waiting for a name\n").await?;
+                    return Ok(());
+                }
+            },
+            // Server is going away before the user could join. Nothing to clean up.
+            _ = shutdown.wait() => return Ok(()),
+        };
         info!("{self_username} joined");
 
+        let result = self.chat(&self_username).await;
+
+        // User left the chat, even if they could not be written to anymore
+        {
+            let mut users = self.joined_users.write().unwrap();
+            let index = users.binary_search(&self_username).unwrap();
+            users.remove(index);
+        }
+        let _ = self
+            .chat_tx_channel
+            .send(ChatEvent::UserLeft(self_username));
+
+        result
+    }
+
+    /// Relay messages between the user and the room until they leave.
+    async fn chat(&mut self, self_username: &str) -> Result<()> {
+        let shutdown = self.shutdown.clone();
         let mut chat_rx_channel = self.chat_tx_channel.subscribe();
-        let mut messages = self.socket_rx.lines();
+        let mut messages = (&mut self.socket_rx).lines();
+        let idle_timeout = self.idle_timeout;
+        // Only polled when there is an idle timeout
+        let idle = sleep(idle_timeout.unwrap_or_default());
+        tokio::pin!(idle);
         loop {
             tokio::select! {
+                // The user leaves even if they can't be told
+                _ = &mut idle, if idle_timeout.is_some() => {
+                    let _ = self
+                        .socket_tx
+                        .write_all(b"* You have been idle for too long. You have left the room\n")
+                        .await;
+                    break;
+                }
+                _ = shutdown.wait() => {
+                    let _ = self
+                        .socket_tx
+                        .write_all(b"* The server is shutting down. You have left the room\n")
+                        .await;
+                    break;
+                }
                 line = messages.next_line() => {
                     match line {
                         Ok(Some(message)) => {
+                            FRAMES_PARSED.inc();
+                            if let Some(idle_timeout) = idle_timeout {
+                                idle.as_mut().reset(Instant::now() + idle_timeout);
+                            }
                             self.chat_tx_channel
-                                .send(ChatEvent::Message{username: self_username.to_owned(), message})
+                                .send(ChatEvent::Message{username: self_username.to_string(), message})
                                 .unwrap(); // Can't fail as we also hold one receiver
                         },
                         // If we receive invalid UTF-8 or EOF, the user leaves the chat
@@ -77,16 +146,6 @@ impl Connection {
             }
         }
 
-        // User left the chat
-        {
-            let mut users = self.joined_users.write().unwrap();
-            let index = users.binary_search(&self_username).unwrap();
-            users.remove(index);
-        }
-        let _ = self
-            .chat_tx_channel
-            .send(ChatEvent::UserLeft(self_username));
-
         Ok(())
     }
 
@@ -113,7 +172,7 @@ impl Connection {
         if username.is_empty() {
             bail!("Invalid username");
         }
-        if let Some(_) = username.find(|c: char| !c.is_ascii_alphanumeric()) {
+        if username.contains(|c: char| !c.is_ascii_alphanumeric()) {
             bail!("Invalid username");
         }
 
@@ -2,7 +2,9 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 
-use tokio::net::{TcpListener, ToSocketAddrs};
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
+use tokio::net::ToSocketAddrs;
 use tokio::sync::broadcast;
 
 use crate::connection::Connection;
@@ -15,40 +17,52 @@ pub enum ChatEvent {
 }
 
 pub struct Server {
-    listener: TcpListener,
+    server: protohackers_core::server::Server,
     joined_users: Arc<RwLock<Vec<String>>>,
     chat_tx_channel: broadcast::Sender<ChatEvent>,
 }
 
 impl Server {
     pub async fn new(bind_address: impl ToSocketAddrs) -> io::Result<Self> {
-        let listener = TcpListener::bind(bind_address).await?;
+        let server = protohackers_core::server::Server::bind(bind_address).await?;
         let (tx, _rx) = broadcast::channel(16);
         Ok(Self {
-            listener,
+            server,
             joined_users: Arc::new(RwLock::new(Vec::new())),
             chat_tx_channel: tx,
         })
     }
 
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.server = self.server.with_config(config);
+        self
+    }
+
+    /// Handle to stop the server. Users still in the room are told it is going away.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown_handle()
+    }
+
     pub async fn run(self) {
-        loop {
-            let (socket, address) = self.listener.accept().await.unwrap();
-            info!("New connection from {address}");
-
-            let joined_users = Arc::clone(&self.joined_users);
-            let tx = self.chat_tx_channel.clone();
-            tokio::spawn(async move {
-                let connection = Connection::new(socket, joined_users, tx);
-                if let Err(err) = connection.handle().await {
-                    error!("[{address}] Error: {err}");
-                }
-                info!("Disconnecting {address}");
-            });
-        }
+        let joined_users = self.joined_users;
+        let chat_tx_channel = self.chat_tx_channel;
+        let shutdown = self.server.shutdown_handle();
+        let config = self.server.config().clone();
+        self.server
+            .run(move |socket, _address| {
+                let connection = Connection::new(
+                    socket,
+                    Arc::clone(&joined_users),
+                    chat_tx_channel.clone(),
+                    shutdown.clone(),
+                    &config,
+                );
+                connection.handle()
+            })
+            .await;
     }
 
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.listener.local_addr()
+        Ok(self.server.local_addr())
     }
 }
@@ -1,5 +1,7 @@
-use std::net::SocketAddr;
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
 
+use protohackers_core::server::Config;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 
@@ -50,3 +52,123 @@ async fn test_2_clients_connected() {
     connection2.read_line(&mut buffer).await.unwrap();
     assert_eq!(buffer, "[Leo] Hi!\n");
 }
+
+#[tokio::test]
+async fn test_shutdown_notifies_users() {
+    let server = Server::new("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr().unwrap();
+    let shutdown = server.shutdown_handle();
+    let server = tokio::spawn(server.run());
+
+    let connection = TcpStream::connect(address).await.unwrap();
+    let mut connection = BufReader::new(connection);
+    let mut buffer = String::new();
+    connection.read_line(&mut buffer).await.unwrap();
+    connection.write_all("Leo\n".as_bytes()).await.unwrap();
+    buffer.clear();
+    connection.read_line(&mut buffer).await.unwrap();
+    assert_eq!(buffer, "* Chatting now: \n");
+
+    shutdown.trigger();
+
+    buffer.clear();
+    connection.read_line(&mut buffer).await.unwrap();
+    assert_eq!(
+        buffer,
+        "* The server is shutting down. You have left the room\n"
+    );
+    buffer.clear();
+    assert_eq!(connection.read_line(&mut buffer).await.unwrap(), 0);
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_handshake_timeout() {
+    let config = Config {
+        handshake_timeout: Some(Duration::from_millis(100)),
+        ..Config::default()
+    };
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(config);
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let connection = TcpStream::connect(address).await.unwrap();
+    let mut connection = BufReader::new(connection);
+    let mut buffer = String::new();
+    connection.read_line(&mut buffer).await.unwrap();
+
+    buffer.clear();
+    connection.read_line(&mut buffer).await.unwrap();
+    assert_eq!(buffer, "* Timed out waiting for a name\n");
+    buffer.clear();
+    assert_eq!(connection.read_line(&mut buffer).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let config = Config {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(config);
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let connection = TcpStream::connect(address).await.unwrap();
+    let mut connection = BufReader::new(connection);
+    let mut buffer = String::new();
+    connection.read_line(&mut buffer).await.unwrap();
+    connection.write_all("Leo\n".as_bytes()).await.unwrap();
+    connection.read_line(&mut buffer).await.unwrap();
+
+    buffer.clear();
+    connection.read_line(&mut buffer).await.unwrap();
+    assert_eq!(
+        buffer,
+        "* You have been idle for too long. You have left the room\n"
+    );
+    buffer.clear();
+    assert_eq!(connection.read_line(&mut buffer).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_idle_user_not_reading_leaves() {
+    let config = Config {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..Config::default()
+    };
+    let server = Server::new("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(config);
+    let address = server.local_addr().unwrap();
+    tokio::spawn(server.run());
+
+    let connection = TcpStream::connect(address).await.unwrap();
+    let mut connection = BufReader::new(connection);
+    let mut buffer = String::new();
+    connection.read_line(&mut buffer).await.unwrap();
+    connection.write_all("Leo\n".as_bytes()).await.unwrap();
+    connection.read_line(&mut buffer).await.unwrap();
+    // Not told about leaving the room
+    let connection = connection.into_inner().into_std().unwrap();
+    connection.shutdown(Shutdown::Read).unwrap();
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    drop(connection);
+
+    // The name is free again
+    let connection = TcpStream::connect(address).await.unwrap();
+    let mut connection = BufReader::new(connection);
+    connection.read_line(&mut buffer).await.unwrap();
+    connection.write_all("Leo\n".as_bytes()).await.unwrap();
+    buffer.clear();
+    connection.read_line(&mut buffer).await.unwrap();
+    assert_eq!(buffer, "* Chatting now: \n");
+}
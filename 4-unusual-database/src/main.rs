@@ -1,31 +1,52 @@
 use std::collections::HashMap;
 
 use anyhow::Result;
+use clap::Parser;
+use log::info;
 use tokio::net::UdpSocket;
 
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::metrics::{BYTES_RECEIVED, BYTES_SENT, FRAMES_PARSED};
+use protohackers_core::{cli, logging, metrics};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(flatten)]
+    server: ServerArgs,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let address = "0.0.0.0:9004";
-    let socket = UdpSocket::bind(address).await?;
+    let cli: Cli = cli::parse(9004);
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
+
+    let socket = UdpSocket::bind(cli.server.bind_address()).await?;
+    info!("Starting server at {}", socket.local_addr()?);
     let mut storage = HashMap::new();
     let mut buffer = [0u8; 1000];
 
     loop {
         let (len, client) = socket.recv_from(&mut buffer).await?;
+        BYTES_RECEIVED.inc_by(len as u64);
         let message = String::from_utf8(buffer[..len].to_vec())?;
+        FRAMES_PARSED.inc();
         match message.split_once('=') {
             // Query
             None => {
-                if message == "version" {
+                let sent = if message == "version" {
                     socket
                         .send_to("version=Ken's Key-Value Store 1.0".as_bytes(), client)
-                        .await?;
+                        .await?
                 } else {
                     let value = storage.get(&message).cloned().unwrap_or_default();
                     socket
                         .send_to(format!("{message}={value}").as_bytes(), client)
-                        .await?;
-                }
+                        .await?
+                };
+                BYTES_SENT.inc_by(sent as u64);
             }
 
             // Insert
@@ -1,33 +1,55 @@
 use std::borrow::Cow;
 use std::mem;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use anyhow::Result;
 use fancy_regex::Regex;
 use lazy_static::lazy_static;
+use protohackers_core::metrics::{Metered, FRAMES_PARSED};
+use protohackers_core::shutdown::Shutdown;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
+use tokio::time::{sleep, Instant};
 
 const TONY_ADDRESS: &str = "7YWHMfk9JZe0LM0g1ZauHuiSxhI";
 const UPSTREAM: &str = "chat.protohackers.com:16963";
 
-pub async fn handle_new_connection(mut socket: TcpStream, address: SocketAddr) -> Result<()> {
+pub async fn handle_new_connection(
+    mut socket: TcpStream,
+    address: SocketAddr,
+    shutdown: Shutdown,
+    idle_timeout: Option<Duration>,
+) -> Result<()> {
     let mut upstream_socket = TcpStream::connect(UPSTREAM).await?;
     let (upstream_rx, mut upstream_tx) = upstream_socket.split();
     let upstream_rx_buffer = BufReader::new(upstream_rx);
     let mut upstream_lines = upstream_rx_buffer.lines();
 
-    let (client_rx, mut client_tx) = socket.split();
-    let mut client_rx_buffer = BufReader::new(client_rx);
+    let (client_rx, client_tx) = socket.split();
+    let mut client_tx = Metered::new(client_tx);
+    let mut client_rx_buffer = BufReader::new(Metered::new(client_rx));
     let mut incoming_buffer = Vec::new();
+    // Traffic in either direction keeps the session alive
+    let idle = sleep(idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
 
     loop {
+        if let Some(idle_timeout) = idle_timeout {
+            idle.as_mut().reset(Instant::now() + idle_timeout);
+        }
         tokio::select! {
+            _ = shutdown.wait() => break,
+            _ = &mut idle, if idle_timeout.is_some() => {
+                info!("{address} idle for too long");
+                break;
+            }
             res = client_rx_buffer.read_until(b'\n', &mut incoming_buffer) => {
                 match res {
                     // EOF
                     Ok(0) => break,
                     Ok(_) => {
+                        FRAMES_PARSED.inc();
                         let line_vec = mem::take(&mut incoming_buffer);
                         let line = String::from_utf8(line_vec)?;
                         debug!("{address} Received: {}", line.trim_end());
@@ -53,7 +75,7 @@ pub async fn handle_new_connection(mut socket: TcpStream, address: SocketAddr) -
     Ok(())
 }
 
-fn rewrite_boguscoin_address(input: &str) -> Cow<str> {
+fn rewrite_boguscoin_address(input: &str) -> Cow<'_, str> {
     lazy_static! {
         static ref RE: Regex = Regex::new(r"(?<= |^)7[\d\w]{25,34}(?= |$|\n)").unwrap();
     }
@@ -1,36 +1,29 @@
-use std::net::Ipv4Addr;
-
 use anyhow::Result;
 use clap::Parser;
-use clap_verbosity_flag::{InfoLevel, Verbosity};
 use log::info;
 
 use mob_in_the_middle::server::Server;
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::{cli, logging, metrics};
 
 #[derive(Parser, Debug)]
 struct Args {
-    /// Host to bind to
-    #[arg(short = 'H', long, default_value_t = Ipv4Addr::from(0))]
-    pub host: Ipv4Addr,
-
-    /// Port to listen
-    #[arg(short, long, default_value_t = 9005)]
-    pub port: u16,
-
-    #[clap(flatten)]
-    verbose: Verbosity<InfoLevel>,
+    #[command(flatten)]
+    server: ServerArgs,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Args = Args::parse();
-    env_logger::Builder::new()
-        .filter_level(args.verbose.log_level_filter())
-        .parse_default_env()
-        .init();
-
-    let bind_address = (args.host, args.port);
-    let server = Server::new(bind_address).await?;
+    let args: Args = cli::parse(9005);
+    logging::init(&args.server.verbose)?;
+    if let Some(address) = args.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
+
+    let server = Server::new(args.server.bind_address())
+        .await?
+        .with_config(args.server.server_config());
+    server.shutdown_handle().trigger_on_signal();
     info!("Proxy started at {}", server.local_addr());
     server.run().await;
 
@@ -1,36 +1,45 @@
 use std::net::SocketAddr;
 
 use anyhow::Result;
-use tokio::net::{TcpListener, ToSocketAddrs};
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
+use tokio::net::ToSocketAddrs;
 
 use crate::connection::handle_new_connection;
 
 pub struct Server {
-    listener: TcpListener,
+    server: protohackers_core::server::Server,
 }
 
 impl Server {
     pub async fn new(address: impl ToSocketAddrs) -> Result<Self> {
         Ok(Server {
-            listener: TcpListener::bind(address).await?,
+            server: protohackers_core::server::Server::bind(address).await?,
         })
     }
 
     /// Returns the local address that the Server is bound to.
     pub fn local_addr(&self) -> SocketAddr {
-        self.listener.local_addr().unwrap()
+        self.server.local_addr()
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.server = self.server.with_config(config);
+        self
+    }
+
+    /// Handle to stop the proxy. Open sessions are disconnected from both ends.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown_handle()
     }
 
     pub async fn run(self) {
-        loop {
-            let (socket, address) = self.listener.accept().await.unwrap();
-            info!("New client connected from {address}");
-
-            tokio::spawn(async move {
-                if let Err(err) = handle_new_connection(socket, address).await {
-                    error!("{address} Error: {err}");
-                }
-            });
-        }
+        let shutdown = self.server.shutdown_handle();
+        let idle_timeout = self.server.config().idle_timeout;
+        self.server
+            .run(move |socket, address| {
+                handle_new_connection(socket, address, shutdown.clone(), idle_timeout)
+            })
+            .await;
     }
 }
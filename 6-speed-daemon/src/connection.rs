@@ -1,25 +1,66 @@
+use std::io;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use anyhow::{bail, Result};
+use protohackers_core::limits::Rejection;
+use protohackers_core::metrics::Metered;
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
 use tokio::io::{AsyncWriteExt, BufReader, BufWriter};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant};
 
 use crate::frame::{ClientFrame, ServerFrame};
 use crate::heartbeat::create_heartbeat;
-use crate::road_map::{IslandMap, PlateObservation, ProcessorCommand};
+use crate::road_map::{IslandMap, PlateObservation, ProcessorCommand, Ticket};
 
-#[instrument(skip(socket, map))]
+/// How long the writer gets to send the last error before the connection is dropped.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[instrument(skip(socket, map, shutdown, config))]
 pub(crate) async fn handle_new_connection(
     socket: TcpStream,
     address: SocketAddr,
     map: IslandMap,
+    shutdown: Shutdown,
+    config: Config,
 ) -> Result<()> {
     let mut connection = ConnectionHandler::new(socket);
+    let handshake_deadline = config
+        .handshake_timeout
+        .map(|timeout| Instant::now() + timeout);
 
     loop {
-        let frame = match connection.read_frame().await {
+        // Clients must identify themselves in time. After that, only cameras can go idle,
+        // as dispatchers are not expected to send anything.
+        let deadline = match connection.client_type {
+            None => handshake_deadline,
+            Some(ClientType::Camera { .. }) => {
+                config.idle_timeout.map(|timeout| Instant::now() + timeout)
+            }
+            Some(ClientType::Dispatcher) => None,
+        };
+        let frame = tokio::select! {
+            frame = connection.read_frame() => frame,
+            _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                let reason = if connection.client_type.is_none() {
+                    "Timed out waiting for IAmCamera or IAmDispatcher"
+                } else {
+                    "Idle timeout"
+                };
+                info!(%address, reason, "Closing connection");
+                return connection.close_with_error(reason).await;
+            }
+            _ = shutdown.wait() => {
+                info!(%address, "Server shutting down. Flushing pending tickets");
+                return connection.flush_and_close().await;
+            }
+        };
+        let frame = match frame {
             Ok(frame) => frame,
             Err(err) => {
                 let _ = connection
@@ -49,24 +90,27 @@ pub(crate) async fn handle_new_connection(
                 let ticket_channels = rx.await.unwrap();
                 for ch in ticket_channels {
                     let write_channel = connection.write_channel.clone();
-                    tokio::spawn(async move {
-                        loop {
-                            let ticket = ch.recv().await.unwrap();
-                            debug!("Dispatching new ticket. ticket={:?}", ticket);
-                            let ticket_frame = ServerFrame::Ticket {
-                                plate: ticket.plate,
-                                road: ticket.road,
-                                mile1: ticket.mile1,
-                                timestamp1: ticket.timestamp1,
-                                mile2: ticket.mile2,
-                                timestamp2: ticket.timestamp2,
-                                speed: ticket.speed,
-                            };
-                            if write_channel.send(ticket_frame).await.is_err() {
-                                info!("Client disconnected before the ticket was dispatched. client={address}");
-                            };
+                    let shutdown = shutdown.clone();
+                    let forwarder = tokio::spawn({
+                        let ch = ch.clone();
+                        async move {
+                            loop {
+                                // Stop taking tickets on shutdown. The connection flushes
+                                // whatever is left in the channel.
+                                let ticket = tokio::select! {
+                                    biased;
+                                    _ = shutdown.wait() => return,
+                                    ticket = ch.recv() => ticket.unwrap(),
+                                };
+                                debug!("Dispatching new ticket. ticket={:?}", ticket);
+                                if write_channel.send(ticket.into()).await.is_err() {
+                                    info!("Client disconnected before the ticket was dispatched. client={address}");
+                                };
+                            }
                         }
                     });
+                    connection.ticket_channels.push(ch);
+                    connection.ticket_forwarders.push(forwarder);
                 }
             }
             Some(ClientFrame::Plate { plate, timestamp }) => match connection.client_type {
@@ -90,7 +134,11 @@ pub(crate) async fn handle_new_connection(
                     connection.error("Heartbeat already set").await;
                 } else {
                     connection.heartbeat_set = true;
-                    tokio::spawn(create_heartbeat(interval, connection.write_channel.clone()));
+                    tokio::spawn(create_heartbeat(
+                        interval,
+                        connection.write_channel.clone(),
+                        shutdown.clone(),
+                    ));
                 };
             }
         };
@@ -106,23 +154,30 @@ enum ClientType {
 }
 
 struct ConnectionHandler {
-    read_socket: BufReader<OwnedReadHalf>,
+    read_socket: BufReader<Metered<OwnedReadHalf>>,
     write_channel: mpsc::Sender<ServerFrame>,
+    writer: JoinHandle<Result<()>>,
     client_type: Option<ClientType>,
     heartbeat_set: bool,
+    /// Tickets to be sent to this client, if it is a dispatcher
+    ticket_channels: Vec<async_channel::Receiver<Ticket>>,
+    ticket_forwarders: Vec<JoinHandle<()>>,
 }
 
 impl ConnectionHandler {
     pub fn new(socket: TcpStream) -> Self {
         let (read, write) = socket.into_split();
         let (write_channel_tx, write_channel_rx) = mpsc::channel(16);
-        tokio::spawn(write_frame(write_channel_rx, write));
+        let writer = tokio::spawn(write_frame(write_channel_rx, write));
 
         Self {
-            read_socket: BufReader::new(read),
+            read_socket: BufReader::new(Metered::new(read)),
             write_channel: write_channel_tx,
+            writer,
             client_type: None,
             heartbeat_set: false,
+            ticket_channels: Vec::new(),
+            ticket_forwarders: Vec::new(),
         }
     }
 
@@ -147,6 +202,41 @@ impl ConnectionHandler {
         ClientFrame::parse(&mut self.read_socket).await
     }
 
+    /// Send the tickets still waiting to be dispatched and close the connection
+    /// once everything was written to the socket.
+    ///
+    /// Every other task writing to this connection must have been stopped already.
+    pub async fn flush_and_close(self) -> Result<()> {
+        for forwarder in self.ticket_forwarders {
+            let _ = forwarder.await;
+        }
+        for ch in &self.ticket_channels {
+            while let Ok(ticket) = ch.try_recv() {
+                debug!("Flushing ticket. ticket={:?}", ticket);
+                if self.write_channel.send(ticket.into()).await.is_err() {
+                    // Client is already gone
+                    return Ok(());
+                }
+            }
+        }
+
+        // The writer finishes once the last sender is dropped
+        drop(self.write_channel);
+        self.writer.await?
+    }
+
+    /// Send an error to the client and close the connection.
+    pub async fn close_with_error(mut self, error_msg: &str) -> Result<()> {
+        self.error(error_msg).await;
+        drop(self.write_channel);
+        // A running heartbeat keeps the writer alive, so it can't be waited for unconditionally
+        if let Ok(result) = tokio::time::timeout(CLOSE_TIMEOUT, &mut self.writer).await {
+            return result?;
+        }
+        self.writer.abort();
+        Ok(())
+    }
+
     pub async fn error(&self, error_msg: &str) {
         self.write_channel
             .send(ServerFrame::Error(error_msg.as_bytes().to_vec()))
@@ -160,42 +250,20 @@ async fn write_frame(
     mut channel: mpsc::Receiver<ServerFrame>,
     socket: OwnedWriteHalf,
 ) -> Result<()> {
-    let mut socket = BufWriter::new(socket);
-    loop {
-        match channel.recv().await {
-            Some(ServerFrame::Error(err)) => {
-                socket.write_u8(0x10).await?;
-                socket.write_u8(err.len() as u8).await?;
-                socket.write_all(&err).await?;
-                socket.flush().await?;
-            }
-            Some(ServerFrame::Ticket {
-                plate,
-                road,
-                mile1,
-                timestamp1,
-                mile2,
-                timestamp2,
-                speed,
-            }) => {
-                socket.write_u8(0x21).await?;
-                socket.write_u8(plate.len() as u8).await?;
-                socket.write_all(&plate).await?;
-                socket.write_u16(road).await?;
-                socket.write_u16(mile1).await?;
-                socket.write_u32(timestamp1).await?;
-                socket.write_u16(mile2).await?;
-                socket.write_u32(timestamp2).await?;
-                socket.write_u16(speed).await?;
-                socket.flush().await?;
-            }
-            Some(ServerFrame::Heartbeat) => {
-                socket.write_u8(0x41).await?;
-                socket.flush().await?;
-            }
-            // Connection was closed
-            None => break,
-        };
+    let mut socket = BufWriter::new(Metered::new(socket));
+    // Loop ends when the connection was closed
+    while let Some(frame) = channel.recv().await {
+        frame.write(&mut socket).await?;
+        socket.flush().await?;
     }
     Ok(())
 }
+
+/// Tell a client refused by the connection limits why, before closing the connection.
+pub(crate) async fn reject_connection(socket: TcpStream, rejection: Rejection) -> io::Result<()> {
+    let mut socket = Metered::new(socket);
+    ServerFrame::Error(rejection.to_string().into_bytes())
+        .write(&mut socket)
+        .await?;
+    socket.shutdown().await
+}
@@ -1,7 +1,11 @@
+use std::io;
 use std::io::ErrorKind;
 
 use anyhow::{anyhow, bail, Result};
-use tokio::io::{AsyncBufRead, AsyncReadExt};
+use protohackers_core::metrics::{FRAMES_PARSED, PARSE_ERRORS};
+use tokio::io::{AsyncBufRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::road_map::Ticket;
 
 #[derive(Debug)]
 pub(crate) enum ClientFrame {
@@ -54,9 +58,13 @@ impl ClientFrame {
             }
 
             // Unknown message type. Return error
-            _e => bail!("Unknown error: {_e:?}"),
+            _e => {
+                PARSE_ERRORS.inc();
+                bail!("Unknown error: {_e:?}")
+            }
         };
         debug!("Parsed new frame: {:?}", frame);
+        FRAMES_PARSED.inc();
         Ok(Some(frame))
     }
 }
@@ -84,3 +92,53 @@ pub(crate) enum ServerFrame {
     },
     Heartbeat,
 }
+
+impl ServerFrame {
+    /// Serialize the frame into `dst`. The caller is responsible for flushing it.
+    pub async fn write<W: AsyncWrite + Unpin>(&self, dst: &mut W) -> io::Result<()> {
+        match self {
+            ServerFrame::Error(err) => {
+                dst.write_u8(0x10).await?;
+                dst.write_u8(err.len() as u8).await?;
+                dst.write_all(err).await?;
+            }
+            ServerFrame::Ticket {
+                plate,
+                road,
+                mile1,
+                timestamp1,
+                mile2,
+                timestamp2,
+                speed,
+            } => {
+                dst.write_u8(0x21).await?;
+                dst.write_u8(plate.len() as u8).await?;
+                dst.write_all(plate).await?;
+                dst.write_u16(*road).await?;
+                dst.write_u16(*mile1).await?;
+                dst.write_u32(*timestamp1).await?;
+                dst.write_u16(*mile2).await?;
+                dst.write_u32(*timestamp2).await?;
+                dst.write_u16(*speed).await?;
+            }
+            ServerFrame::Heartbeat => {
+                dst.write_u8(0x41).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<Ticket> for ServerFrame {
+    fn from(ticket: Ticket) -> Self {
+        ServerFrame::Ticket {
+            plate: ticket.plate,
+            road: ticket.road,
+            mile1: ticket.mile1,
+            timestamp1: ticket.timestamp1,
+            mile2: ticket.mile2,
+            timestamp2: ticket.timestamp2,
+            speed: ticket.speed,
+        }
+    }
+}
@@ -1,13 +1,22 @@
 use std::time::Duration;
 
+use protohackers_core::shutdown::Shutdown;
 use tokio::sync::mpsc;
 
 use crate::frame::ServerFrame;
 
-pub(crate) async fn create_heartbeat(interval: u32, channel: mpsc::Sender<ServerFrame>) {
+pub(crate) async fn create_heartbeat(
+    interval: u32,
+    channel: mpsc::Sender<ServerFrame>,
+    shutdown: Shutdown,
+) {
     let mut timer = tokio::time::interval(Duration::from_millis(interval as u64 * 100));
     loop {
-        timer.tick().await;
+        tokio::select! {
+            _ = timer.tick() => {}
+            // Let the connection close its socket
+            _ = shutdown.wait() => return,
+        }
         if channel.send(ServerFrame::Heartbeat).await.is_err() {
             // Connection is closed
             return;
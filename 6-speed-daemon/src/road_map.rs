@@ -2,11 +2,25 @@ use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
 use std::sync::{Arc, RwLock};
 
+use protohackers_core::metrics::{self, Counter};
 use tokio::sync::{mpsc, oneshot};
 
 type RoadNumber = u16;
 type Speed = u16;
 
+static TICKETS_ISSUED: Counter = Counter::new(
+    "speed_daemon_tickets_issued_total",
+    "Tickets sent to the dispatchers",
+);
+static TICKETS_SUPPRESSED: Counter = Counter::new(
+    "speed_daemon_tickets_suppressed_total",
+    "Tickets discarded because the car was already ticketed on that day",
+);
+
+pub(crate) fn register_metrics() {
+    metrics::register(&[&TICKETS_ISSUED, &TICKETS_SUPPRESSED]);
+}
+
 pub struct Ticket {
     pub plate: Vec<u8>,
     pub road: u16,
@@ -138,6 +152,10 @@ async fn ticket_processor(mut rx: mpsc::Receiver<ProcessorCommand>) {
                         ticket, ticket_day1, ticket_day2
                     );
                     channel.0.send(ticket).await.unwrap();
+                    TICKETS_ISSUED.inc();
+                } else {
+                    debug!("Car already ticketed on that day. ticket={:?}", ticket);
+                    TICKETS_SUPPRESSED.inc();
                 }
             }
             ProcessorCommand::NewDispatcher { roads, ch } => {
@@ -169,7 +187,7 @@ async fn check_road_plates(
 
     while let Some(plate) = rx.recv().await {
         debug!("Seen new plate. road={}, plate={:?}", road_number, plate);
-        let other_observations = seen_plates.entry(plate.plate.clone()).or_insert(Vec::new());
+        let other_observations = seen_plates.entry(plate.plate.clone()).or_default();
         for (mile, timestamp) in other_observations.iter() {
             let mut obs = [(*mile, *timestamp), (plate.mile, plate.time)];
             obs.sort_by_key(|x| x.1);
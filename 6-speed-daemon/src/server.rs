@@ -1,33 +1,53 @@
 use std::net::SocketAddr;
 
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
 use tokio::net::TcpListener;
 
-use crate::connection::handle_new_connection;
-use crate::road_map::IslandMap;
+use crate::connection::{handle_new_connection, reject_connection};
+use crate::road_map::{self, IslandMap};
 
 pub struct Server {
-    listener: TcpListener,
+    server: protohackers_core::server::Server,
 }
 
 impl Server {
     pub fn new(listener: TcpListener) -> Self {
-        Self { listener }
+        Self {
+            server: protohackers_core::server::Server::new(listener),
+        }
     }
 
     pub fn local_addr(&self) -> SocketAddr {
-        self.listener.local_addr().unwrap()
+        self.server.local_addr()
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.server = self.server.with_config(config);
+        self
+    }
+
+    /// Handle to stop the server. Dispatchers get their pending tickets before disconnecting.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown_handle()
     }
 
     pub async fn run(self) {
+        road_map::register_metrics();
         let island_map = IslandMap::new();
-        loop {
-            let (socket, address) = self.listener.accept().await.unwrap();
-            let map = island_map.clone();
-            tokio::spawn(async move {
-                if let Err(error_message) = handle_new_connection(socket, address, map).await {
-                    error!(%error_message, %address, "Client disconnect");
-                };
-            });
-        }
+        let shutdown = self.server.shutdown_handle();
+        let config = self.server.config().clone();
+        self.server
+            .with_reject_handler(reject_connection)
+            .run(move |socket, address| {
+                handle_new_connection(
+                    socket,
+                    address,
+                    island_map.clone(),
+                    shutdown.clone(),
+                    config.clone(),
+                )
+            })
+            .await;
     }
 }
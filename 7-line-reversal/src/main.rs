@@ -1,33 +1,25 @@
-use std::net::Ipv4Addr;
-
 use clap::Parser;
-use clap_verbosity_flag::{InfoLevel, Verbosity};
 use log::info;
 
 use line_reversal::server::Server;
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::{cli, logging, metrics};
 
 #[derive(Parser)]
 struct Cli {
-    #[clap(long, default_value_t = Ipv4Addr::from(0))]
-    pub host: Ipv4Addr,
-
-    #[clap(short, long, default_value_t = 9006)]
-    pub port: u16,
-
-    #[clap(flatten)]
-    verbose: Verbosity<InfoLevel>,
+    #[command(flatten)]
+    server: ServerArgs,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli: Cli = Cli::parse();
-    env_logger::Builder::new()
-        .filter_level(cli.verbose.log_level_filter())
-        .parse_default_env()
-        .init();
+    let cli: Cli = cli::parse(9006);
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
 
-    let address = (cli.host, cli.port);
-    let mut server = Server::new(address).await?;
+    let mut server = Server::new(cli.server.bind_address()).await?;
     info!("Starting server at {}", server.local_addr());
     server.run().await?;
     Ok(())
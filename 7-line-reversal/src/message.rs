@@ -4,7 +4,7 @@ use regex::Regex;
 pub type SessionId = u32;
 
 #[derive(Debug)]
-pub struct InvalidMessage(&'static str);
+pub struct InvalidMessage(pub &'static str);
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum Message {
@@ -3,11 +3,12 @@ use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use protohackers_core::metrics::{self, BYTES_RECEIVED, BYTES_SENT, FRAMES_PARSED, PARSE_ERRORS};
 use tokio::net::{ToSocketAddrs, UdpSocket};
 use tokio::sync::mpsc;
 
 use crate::message::{Message, SessionId};
-use crate::session::Session;
+use crate::session::{Session, RETRANSMISSIONS};
 
 pub struct Server {
     socket: Arc<UdpSocket>,
@@ -29,12 +30,15 @@ impl Server {
     }
 
     pub async fn run(&mut self) -> Result<(), io::Error> {
+        metrics::register(&[&RETRANSMISSIONS]);
         let mut buffer = vec![0u8; 1000];
 
         loop {
             let (len, peer_address) = self.socket.recv_from(&mut buffer).await?;
+            BYTES_RECEIVED.inc_by(len as u64);
             match Message::try_from(&buffer[0..len]) {
                 Err(err) => {
+                    PARSE_ERRORS.inc();
                     warn!(
                         "Received invalid packet: err={:?}, packet={:?}",
                         err,
@@ -43,6 +47,7 @@ impl Server {
                     continue;
                 }
                 Ok(message) => {
+                    FRAMES_PARSED.inc();
                     let session_id = message.session_id();
                     let tx = self.sessions.entry(session_id).or_insert_with(|| {
                         let (tx, rx) = mpsc::channel(16);
@@ -51,14 +56,17 @@ impl Server {
                         tokio::spawn(async move { session.run().await });
                         tx
                     });
-                    if let Err(_) = tx.send(message).await {
+                    if tx.send(message).await.is_err() {
                         error!(
                             "Error sending message to Session. Session was already disconnected. peer_addr={peer_address}"
                         );
-                        let _ = self
+                        if let Ok(sent) = self
                             .socket
                             .send_to(&Message::Disconnect(session_id).to_vec(), peer_address)
-                            .await;
+                            .await
+                        {
+                            BYTES_SENT.inc_by(sent as u64);
+                        }
                     }
                 }
             };
@@ -2,11 +2,17 @@ use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::time::Duration;
 
+use protohackers_core::metrics::{Counter, BYTES_SENT};
 use tokio::net::UdpSocket;
 use tokio::sync::{broadcast, mpsc};
 
 use crate::message::{Message, SessionId};
 
+pub(crate) static RETRANSMISSIONS: Counter = Counter::new(
+    "line_reversal_retransmissions_total",
+    "Data messages sent again because the peer did not ack them in time",
+);
+
 enum Error {
     Disconnect,
 }
@@ -188,18 +194,22 @@ impl Session {
             position,
         };
         info!("Sending ACK. {:?}", ack_message);
-        self.socket
+        let sent = self
+            .socket
             .send_to(&ack_message.to_vec(), self.peer_address)
             .await
             .unwrap();
+        BYTES_SENT.inc_by(sent as u64);
     }
 
     async fn close(&mut self) -> Result<(), Error> {
         let close_message = Message::Disconnect(self.id);
-        self.socket
+        let sent = self
+            .socket
             .send_to(&close_message.to_vec(), self.peer_address)
             .await
             .unwrap();
+        BYTES_SENT.inc_by(sent as u64);
         self.rx.close();
         let _ = self.timeout_tx.send(());
 
@@ -222,6 +232,8 @@ async fn send_messages(
     let mut retransmission_timeout = tokio::time::interval(Duration::from_secs(3));
     let mut session_timeout = tokio::time::interval(Duration::from_secs(20));
     session_timeout.reset();
+    // The first tick of the retransmission timer is the original transmission
+    let mut is_retransmission = false;
 
     loop {
         tokio::select! {
@@ -250,13 +262,18 @@ async fn send_messages(
                         if last_ack <= *position {
                             all_messages_acked = false;
                             // debug!("(8) Sending data {:?}", message);
-                            socket.send_to(&message.to_vec(), peer_address).await.unwrap();
+                            let sent = socket.send_to(&message.to_vec(), peer_address).await.unwrap();
+                            BYTES_SENT.inc_by(sent as u64);
+                            if is_retransmission {
+                                RETRANSMISSIONS.inc();
+                            }
                         }
                     }
                 }
                 if all_messages_acked {
                     break;
                 }
+                is_retransmission = true;
             }
         }
     }
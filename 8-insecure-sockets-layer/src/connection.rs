@@ -1,6 +1,10 @@
 use std::net::SocketAddr;
 
 use anyhow::bail;
+use protohackers_core::metrics::{Metered, FRAMES_PARSED, PARSE_ERRORS};
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
+use protohackers_core::timeout::timeout;
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
 use tokio::net::TcpStream;
 use tracing::{info, instrument};
@@ -9,22 +13,47 @@ use crate::cipher_stream::CipherStream;
 use crate::ciphers::{AddN, AddPos, Cipher, ReverseBits, XorN, XorPos};
 use crate::toy_workshop::prioritise_work;
 
-#[instrument(skip(socket))]
+#[instrument(skip(socket, shutdown, config))]
 pub async fn handle_new_connection(
-    mut socket: TcpStream,
+    socket: TcpStream,
     _address: SocketAddr,
+    shutdown: Shutdown,
+    config: Config,
 ) -> anyhow::Result<()> {
-    let cipher_spec = read_cipher_spec(&mut socket).await?;
+    let mut socket = Metered::new(socket);
+    let cipher_spec = timeout(config.handshake_timeout, read_cipher_spec(&mut socket)).await;
+    let cipher_spec = match cipher_spec {
+        Ok(Ok(cipher_spec)) => cipher_spec,
+        Err(_) => bail!("Timed out waiting for the cipher spec"),
+        Ok(Err(err)) => {
+            PARSE_ERRORS.inc();
+            return Err(err);
+        }
+    };
     let mut cipher_stream = BufStream::new(CipherStream::new(cipher_spec, socket));
 
     loop {
         let mut toys_request = String::new();
-        match cipher_stream.read_line(&mut toys_request).await {
+        let line = tokio::select! {
+            line = timeout(config.idle_timeout, cipher_stream.read_line(&mut toys_request)) => match line {
+                Ok(line) => line,
+                Err(_) => {
+                    info!("Closing idle connection");
+                    return Ok(());
+                }
+            },
+            _ = shutdown.wait() => {
+                info!("Server shutting down");
+                return Ok(());
+            }
+        };
+        match line {
             Ok(0) => {
                 // Reached EOF
                 return Ok(());
             }
             Ok(_) => {
+                FRAMES_PARSED.inc();
                 info!(?toys_request, "Received line");
                 let toys_request = toys_request.trim_end();
 
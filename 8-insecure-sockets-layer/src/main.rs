@@ -1,50 +1,28 @@
-use std::net::Ipv4Addr;
-
 use clap::Parser;
-use clap_verbosity_flag::{InfoLevel, Verbosity};
 use tokio::net::TcpListener;
+use tracing::info;
 
 use insecure_sockets_layer::server::Server;
-use tracing::info;
-use tracing_subscriber::prelude::*;
-use tracing_subscriber::Layer;
+use protohackers_core::cli::ServerArgs;
+use protohackers_core::{cli, logging, metrics};
 
 #[derive(Parser, Debug)]
 struct Cli {
-    #[clap(long, default_value_t = Ipv4Addr::from(0))]
-    pub host: Ipv4Addr,
-
-    #[clap(short, long, default_value_t = 9008)]
-    pub port: u16,
-
-    #[clap(flatten)]
-    verbose: Verbosity<InfoLevel>,
-}
-
-fn convert_level_filter(filter: log::LevelFilter) -> tracing_subscriber::filter::LevelFilter {
-    match filter {
-        log::LevelFilter::Off => tracing_subscriber::filter::LevelFilter::OFF,
-        log::LevelFilter::Error => tracing_subscriber::filter::LevelFilter::ERROR,
-        log::LevelFilter::Warn => tracing_subscriber::filter::LevelFilter::WARN,
-        log::LevelFilter::Info => tracing_subscriber::filter::LevelFilter::INFO,
-        log::LevelFilter::Debug => tracing_subscriber::filter::LevelFilter::DEBUG,
-        log::LevelFilter::Trace => tracing_subscriber::filter::LevelFilter::TRACE,
-    }
+    #[command(flatten)]
+    server: ServerArgs,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli: Cli = Cli::parse();
-
-    let subscriber_stdout = tracing_subscriber::fmt::layer()
-        .with_filter(convert_level_filter(cli.verbose.log_level_filter()));
-    tracing_subscriber::registry()
-        .with(subscriber_stdout)
-        .try_init()?;
+    let cli: Cli = cli::parse(9008);
+    logging::init(&cli.server.verbose)?;
+    if let Some(address) = cli.server.metrics_address() {
+        metrics::serve(address).await?;
+    }
 
-    let bind_address = (cli.host, cli.port);
-    let listener = TcpListener::bind(bind_address).await?;
-    let server = Server::new(listener);
+    let listener = TcpListener::bind(cli.server.bind_address()).await?;
+    let server = Server::new(listener).with_config(cli.server.server_config());
+    server.shutdown_handle().trigger_on_signal();
     info!(address = server.local_addr().to_string(), "Starting server");
     server.run().await;
 
@@ -1,31 +1,43 @@
 use std::net::SocketAddr;
 
+use protohackers_core::server::Config;
+use protohackers_core::shutdown::Shutdown;
 use tokio::net::TcpListener;
-use tracing::error;
 
 use crate::connection::handle_new_connection;
 
 pub struct Server {
-    listener: TcpListener,
+    server: protohackers_core::server::Server,
 }
 
 impl Server {
     pub fn new(listener: TcpListener) -> Self {
-        Self { listener }
+        Self {
+            server: protohackers_core::server::Server::new(listener),
+        }
     }
 
     pub fn local_addr(&self) -> SocketAddr {
-        self.listener.local_addr().unwrap()
+        self.server.local_addr()
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.server = self.server.with_config(config);
+        self
+    }
+
+    /// Handle to stop the server. Requests already received are answered before closing.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.server.shutdown_handle()
     }
 
     pub async fn run(self) {
-        loop {
-            let (socket, address) = self.listener.accept().await.unwrap();
-            tokio::spawn(async move {
-                if let Err(e) = handle_new_connection(socket, address).await {
-                    error!(?address, "Connection closed: {e}");
-                }
-            });
-        }
+        let shutdown = self.server.shutdown_handle();
+        let config = self.server.config().clone();
+        self.server
+            .run(move |socket, address| {
+                handle_new_connection(socket, address, shutdown.clone(), config.clone())
+            })
+            .await;
     }
 }
@@ -0,0 +1,204 @@
+use std::ffi::OsString;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::{Args, Parser};
+use clap_verbosity_flag::{InfoLevel, Verbosity};
+
+use crate::server::Config;
+
+/// Command line options common to every server.
+///
+/// Flatten it into the binary's own `Cli` and parse it with [`parse`], which fills in the
+/// default port of that server.
+#[derive(Args, Debug)]
+// Keep the doc comment above out of the binaries' help
+#[command(about = None, long_about = None)]
+pub struct ServerArgs {
+    /// Host address to bind to
+    #[arg(short = 'H', long, default_value_t = Ipv4Addr::from(0))]
+    pub host: Ipv4Addr,
+
+    /// Port to listen
+    #[arg(short, long)]
+    pub port: u16,
+
+    /// Seconds to wait for open connections to finish when shutting down
+    #[arg(long, default_value_t = 5)]
+    pub drain_timeout: u64,
+
+    /// Maximum number of connections open at the same time
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of connections open at the same time from a single IP address
+    #[arg(long)]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Maximum number of new connections per second from a single IP address
+    #[arg(long)]
+    pub accept_rate_per_ip: Option<u32>,
+
+    /// Seconds a connection may stay idle before being closed. Disabled when not given
+    #[arg(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// Seconds a client has to complete the protocol handshake
+    #[arg(long, default_value_t = 30)]
+    pub handshake_timeout: u64,
+
+    /// Expect a PROXY protocol v1 or v2 header on every connection, as sent by HAProxy
+    #[arg(long)]
+    pub proxy_protocol: bool,
+
+    /// Port of the HTTP metrics endpoint, bound to the same host. Disabled when not given
+    #[arg(long)]
+    pub metrics_port: Option<u16>,
+
+    #[command(flatten)]
+    pub verbose: Verbosity<InfoLevel>,
+}
+
+impl ServerArgs {
+    pub fn bind_address(&self) -> (Ipv4Addr, u16) {
+        (self.host, self.port)
+    }
+
+    pub fn metrics_address(&self) -> Option<(Ipv4Addr, u16)> {
+        self.metrics_port.map(|port| (self.host, port))
+    }
+
+    pub fn server_config(&self) -> Config {
+        Config {
+            drain_timeout: Duration::from_secs(self.drain_timeout),
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            accept_rate_per_ip: self.accept_rate_per_ip,
+            idle_timeout: self.idle_timeout.map(Duration::from_secs),
+            handshake_timeout: Some(Duration::from_secs(self.handshake_timeout)),
+            proxy_protocol: self.proxy_protocol,
+        }
+    }
+}
+
+/// Parse the process arguments into `C`, using `default_port` when `--port` is not given.
+///
+/// `C` must flatten a [`ServerArgs`].
+pub fn parse<C: Parser>(default_port: u16) -> C {
+    parse_from(default_port, std::env::args_os())
+}
+
+/// Same as [`parse`], but reading the arguments from `args`.
+pub fn parse_from<C, I, T>(default_port: u16, args: I) -> C
+where
+    C: Parser,
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = C::command()
+        .mut_arg("port", |port| {
+            port.required(false).default_value(default_port.to_string())
+        })
+        // The last value wins, so flags can override those read from a config file
+        .args_override_self(true)
+        .get_matches_from(args);
+    C::from_arg_matches(&matches).unwrap_or_else(|err| err.exit())
+}
+
+/// Same as [`parse`], but with the options in the TOML file at `config` coming first, so
+/// that the command line overrides them.
+///
+/// Every option of the command line can be set in the file: `max_connections = 100` is the
+/// same as `--max-connections 100`, and `proxy_protocol = true` as `--proxy-protocol`.
+pub fn parse_with_config_file<C: Parser>(default_port: u16, config: &Path) -> anyhow::Result<C> {
+    let contents = std::fs::read_to_string(config)
+        .with_context(|| format!("Could not read config file {}", config.display()))?;
+    let options = config_file_args(&contents)
+        .with_context(|| format!("Invalid config file {}", config.display()))?;
+
+    let mut args = std::env::args_os();
+    let binary = args.next().unwrap_or_default();
+    let args = std::iter::once(binary)
+        .chain(options.into_iter().map(OsString::from))
+        .chain(args);
+    Ok(parse_from(default_port, args))
+}
+
+/// Turn the `key = value` pairs of a TOML config file into the `--key value` flags.
+pub fn config_file_args(contents: &str) -> anyhow::Result<Vec<String>> {
+    let table: toml::Table = contents.parse()?;
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let flag = format!("--{}", key.replace('_', "-"));
+        match value {
+            toml::Value::Boolean(true) => args.push(flag),
+            toml::Value::Boolean(false) => {}
+            toml::Value::String(value) => args.extend([flag, value]),
+            toml::Value::Integer(value) => args.extend([flag, value.to_string()]),
+            toml::Value::Float(value) => args.extend([flag, value.to_string()]),
+            _ => bail!("Unsupported value for {key}: {value}"),
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Parser, Debug)]
+    struct Cli {
+        #[command(flatten)]
+        server: ServerArgs,
+    }
+
+    #[test]
+    fn test_default_port() {
+        let cli: Cli = parse_from(9001, ["server"]);
+        assert_eq!(cli.server.bind_address(), (Ipv4Addr::from(0), 9001));
+    }
+
+    #[test]
+    fn test_config_file_args() {
+        let args = config_file_args(
+            "host = \"127.0.0.1\"\nmax_connections = 10\nproxy_protocol = true\nverbose = false\n",
+        )
+        .unwrap();
+        assert_eq!(
+            args,
+            [
+                "--host",
+                "127.0.0.1",
+                "--max-connections",
+                "10",
+                "--proxy-protocol"
+            ]
+        );
+        assert!(config_file_args("ports = [1, 2]").is_err());
+        assert!(config_file_args("port = ").is_err());
+    }
+
+    #[test]
+    fn test_command_line_overrides_config_file() {
+        let args = [
+            "server",
+            "--port",
+            "1234",
+            "--idle-timeout",
+            "5",
+            "--port",
+            "4321",
+        ];
+        let cli: Cli = parse_from(9001, args);
+        assert_eq!(cli.server.bind_address(), (Ipv4Addr::from(0), 4321));
+        assert_eq!(cli.server.idle_timeout, Some(5));
+    }
+
+    #[test]
+    fn test_override_address() {
+        let cli: Cli = parse_from(9001, ["server", "-H", "127.0.0.1", "--port", "1234"]);
+        assert_eq!(cli.server.bind_address(), (Ipv4Addr::LOCALHOST, 1234));
+    }
+}
@@ -0,0 +1,11 @@
+//! Plumbing shared by every protohackers server: the command line, logging setup and the
+//! TCP accept loop. Each challenge crate only has to provide its per-connection handler.
+
+pub mod cli;
+pub mod limits;
+pub mod logging;
+pub mod metrics;
+pub mod proxy_protocol;
+pub mod server;
+pub mod shutdown;
+pub mod timeout;
@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::server::Config;
+
+/// Why a new connection was refused.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rejection {
+    /// The server reached `max_connections`
+    TooManyConnections,
+    /// The client address reached `max_connections_per_ip`
+    TooManyConnectionsFromIp,
+    /// The client address is opening connections faster than `accept_rate_per_ip`
+    RateLimited,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Rejection::TooManyConnections => "Too many connections",
+            Rejection::TooManyConnectionsFromIp => "Too many connections from this address",
+            Rejection::RateLimited => "Connecting too fast",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Number of open connections by client address
+type ConnectionsByIp = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+/// Decides whether the accept loop may spawn a task for a new connection.
+pub(crate) struct ConnectionLimiter {
+    global: Option<Arc<Semaphore>>,
+    per_ip: Option<(usize, ConnectionsByIp)>,
+    rate: Option<RateLimiter>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            global: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            per_ip: config
+                .max_connections_per_ip
+                .map(|max| (max, Arc::new(Mutex::new(HashMap::new())))),
+            rate: config.accept_rate_per_ip.map(RateLimiter::new),
+        }
+    }
+
+    /// Reserve a slot for a connection from `ip`.
+    ///
+    /// The slot is released when the returned permit is dropped.
+    pub fn admit(&mut self, ip: IpAddr) -> Result<ConnectionPermit, Rejection> {
+        let ip_slot = self.admit_ip(ip)?;
+        // Dropping `ip_slot` on error gives the per IP slot back
+        let global = self.acquire_global()?;
+        Ok(ConnectionPermit {
+            _global: global,
+            _ip_slot: ip_slot,
+        })
+    }
+
+    /// Take a slot of `max_connections` for a connection whose client isn't known yet, like
+    /// one still sending its PROXY protocol header.
+    pub fn reserve(&self) -> Result<Reservation, Rejection> {
+        self.acquire_global().map(Reservation)
+    }
+
+    /// Like [`Self::admit`], for a connection that already holds a [`Reservation`].
+    pub fn admit_reserved(
+        &mut self,
+        ip: IpAddr,
+        reservation: Reservation,
+    ) -> Result<ConnectionPermit, Rejection> {
+        let ip_slot = self.admit_ip(ip)?;
+        Ok(ConnectionPermit {
+            _global: reservation.0,
+            _ip_slot: ip_slot,
+        })
+    }
+
+    /// Apply the limits specific to `ip`.
+    fn admit_ip(&mut self, ip: IpAddr) -> Result<Option<IpSlot>, Rejection> {
+        if let Some(rate) = &mut self.rate {
+            if !rate.check(ip, Instant::now()) {
+                return Err(Rejection::RateLimited);
+            }
+        }
+
+        let Some((max, counts)) = &self.per_ip else {
+            return Ok(None);
+        };
+        let mut counts_guard = counts.lock().unwrap();
+        let count = counts_guard.entry(ip).or_insert(0);
+        if *count >= *max {
+            return Err(Rejection::TooManyConnectionsFromIp);
+        }
+        *count += 1;
+        Ok(Some(IpSlot {
+            ip,
+            counts: Arc::clone(counts),
+        }))
+    }
+
+    fn acquire_global(&self) -> Result<Option<OwnedSemaphorePermit>, Rejection> {
+        match &self.global {
+            None => Ok(None),
+            Some(semaphore) => match Arc::clone(semaphore).try_acquire_owned() {
+                Ok(permit) => Ok(Some(permit)),
+                Err(_) => Err(Rejection::TooManyConnections),
+            },
+        }
+    }
+}
+
+/// Slot in `max_connections` taken before the client address is known.
+pub(crate) struct Reservation(Option<OwnedSemaphorePermit>);
+
+/// Held by a connection task for as long as the connection is open.
+pub(crate) struct ConnectionPermit {
+    _global: Option<OwnedSemaphorePermit>,
+    _ip_slot: Option<IpSlot>,
+}
+
+struct IpSlot {
+    ip: IpAddr,
+    counts: ConnectionsByIp,
+}
+
+impl Drop for IpSlot {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap();
+        if let Some(count) = counts.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                counts.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// Buckets kept before the full ones are forgotten
+const MAX_BUCKETS: usize = 1024;
+
+/// Shortest time between two scans for full buckets, so that their cost doesn't grow with
+/// the number of addresses connecting. Any bucket left alone for that long is full.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Token bucket per client address.
+///
+/// Every address can open `rate` connections per second, with bursts of up to `rate`.
+struct RateLimiter {
+    rate: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+    pruned_at: Option<Instant>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f64,
+            buckets: HashMap::new(),
+            pruned_at: None,
+        }
+    }
+
+    /// Take a token from the bucket of `ip`. Returns whether there was one available.
+    fn check(&mut self, ip: IpAddr, now: Instant) -> bool {
+        let rate = self.rate;
+        let prune_due = self
+            .pruned_at
+            .is_none_or(|pruned_at| now.saturating_duration_since(pruned_at) >= PRUNE_INTERVAL);
+        if self.buckets.len() > MAX_BUCKETS && prune_due {
+            // Forget addresses that would have a full bucket anyway
+            self.buckets.retain(|_, bucket| {
+                now.saturating_duration_since(bucket.updated_at)
+                    .as_secs_f64()
+                    * rate
+                    + bucket.tokens
+                    < rate
+            });
+            self.pruned_at = Some(now);
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: rate,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(rate);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const CLIENT1: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+    const CLIENT2: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::new(2);
+        let start = Instant::now();
+
+        assert!(limiter.check(CLIENT1, start));
+        assert!(limiter.check(CLIENT1, start));
+        assert!(!limiter.check(CLIENT1, start));
+        // Other clients have their own bucket
+        assert!(limiter.check(CLIENT2, start));
+
+        // Half a second refills one token
+        assert!(limiter.check(CLIENT1, start + Duration::from_millis(500)));
+        assert!(!limiter.check(CLIENT1, start + Duration::from_millis(500)));
+    }
+
+    #[test]
+    fn test_rate_limiter_prunes_periodically() {
+        let mut limiter = RateLimiter::new(1);
+        let start = Instant::now();
+        let client = |n: u32| IpAddr::V4(Ipv4Addr::from(n));
+        for n in 0..=MAX_BUCKETS as u32 {
+            assert!(limiter.check(client(n), start));
+        }
+        // Scanned once, while every bucket was still empty
+        assert!(limiter.check(client(5000), start + Duration::from_millis(500)));
+        assert_eq!(limiter.buckets.len(), MAX_BUCKETS + 2);
+        assert!(limiter.check(client(5001), start + Duration::from_millis(900)));
+        assert_eq!(limiter.buckets.len(), MAX_BUCKETS + 3);
+
+        // A second later, the buckets full by then are forgotten
+        assert!(limiter.check(client(5002), start + Duration::from_millis(1600)));
+        assert_eq!(limiter.buckets.len(), 2);
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let config = Config {
+            max_connections_per_ip: Some(1),
+            ..Config::default()
+        };
+        let mut limiter = ConnectionLimiter::new(&config);
+
+        let permit = limiter.admit(CLIENT1).unwrap();
+        assert_eq!(
+            limiter.admit(CLIENT1).err(),
+            Some(Rejection::TooManyConnectionsFromIp)
+        );
+        let _other_permit = limiter.admit(CLIENT2).unwrap();
+
+        drop(permit);
+        assert!(limiter.admit(CLIENT1).is_ok());
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let config = Config {
+            max_connections: Some(1),
+            max_connections_per_ip: Some(1),
+            ..Config::default()
+        };
+        let mut limiter = ConnectionLimiter::new(&config);
+
+        let permit = limiter.admit(CLIENT1).unwrap();
+        assert_eq!(
+            limiter.admit(CLIENT2).err(),
+            Some(Rejection::TooManyConnections)
+        );
+
+        // The rejected connection must not keep its per IP slot
+        drop(permit);
+        assert!(limiter.admit(CLIENT2).is_ok());
+    }
+
+    #[test]
+    fn test_reservation() {
+        let config = Config {
+            max_connections: Some(1),
+            max_connections_per_ip: Some(1),
+            ..Config::default()
+        };
+        let mut limiter = ConnectionLimiter::new(&config);
+
+        let reservation = limiter.reserve().unwrap();
+        assert_eq!(limiter.reserve().err(), Some(Rejection::TooManyConnections));
+        assert_eq!(
+            limiter.admit(CLIENT1).err(),
+            Some(Rejection::TooManyConnections)
+        );
+
+        // The reservation becomes the global slot of the connection
+        let permit = limiter.admit_reserved(CLIENT1, reservation).unwrap();
+        assert!(limiter.reserve().is_err());
+        drop(permit);
+        assert!(limiter.admit(CLIENT1).is_ok());
+    }
+}
@@ -0,0 +1,41 @@
+use clap_verbosity_flag::{InfoLevel, Verbosity};
+use tracing::Subscriber;
+use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Install a global subscriber that logs to stdout.
+///
+/// Records emitted through the `log` crate are forwarded as well.
+pub fn init(verbose: &Verbosity<InfoLevel>) -> anyhow::Result<()> {
+    tracing_subscriber::registry()
+        .with(stdout_layer(verbose))
+        .try_init()?;
+    Ok(())
+}
+
+/// Layer logging to stdout, filtered by the verbosity flags.
+///
+/// `RUST_LOG` takes precedence over the flags when it is set.
+/// Use it directly when composing a subscriber with extra layers.
+pub fn stdout_layer<S>(verbose: &Verbosity<InfoLevel>) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let filter = EnvFilter::builder()
+        .with_default_directive(convert_level_filter(verbose.log_level_filter()).into())
+        .from_env_lossy();
+    tracing_subscriber::fmt::layer().with_filter(filter)
+}
+
+fn convert_level_filter(filter: log::LevelFilter) -> LevelFilter {
+    match filter {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE,
+    }
+}
@@ -0,0 +1,388 @@
+//! Process wide metrics, exposed over HTTP in the Prometheus text format.
+//!
+//! Metrics are `static`s registered once at startup with [`register`]. The ones every server
+//! shares are defined here; protocol specific ones live in their crates.
+
+use std::fmt::Write;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tracing::{debug, info, warn};
+
+/// How long a scraper has to send its request before being disconnected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub static CONNECTIONS_ACTIVE: Gauge = Gauge::new(
+    "protohackers_connections_active",
+    "Number of connections currently open",
+);
+pub static CONNECTIONS_TOTAL: Counter = Counter::new(
+    "protohackers_connections_total",
+    "Number of connections accepted",
+);
+pub static CONNECTIONS_REJECTED: Counter = Counter::new(
+    "protohackers_connections_rejected_total",
+    "Number of connections refused by the connection limits",
+);
+pub static BYTES_RECEIVED: Counter = Counter::new(
+    "protohackers_bytes_received_total",
+    "Bytes received from clients",
+);
+pub static BYTES_SENT: Counter =
+    Counter::new("protohackers_bytes_sent_total", "Bytes sent to clients");
+pub static FRAMES_PARSED: Counter = Counter::new(
+    "protohackers_frames_parsed_total",
+    "Protocol messages successfully parsed",
+);
+pub static PARSE_ERRORS: Counter = Counter::new(
+    "protohackers_parse_errors_total",
+    "Protocol messages that could not be parsed",
+);
+
+static REGISTRY: Mutex<Vec<&'static dyn Metric>> = Mutex::new(Vec::new());
+
+/// Make `metrics` part of the exposition. Registering a metric more than once is a no-op.
+pub fn register(metrics: &[&'static dyn Metric]) {
+    let mut registry = REGISTRY.lock().unwrap();
+    for &metric in metrics {
+        if !metric
+            .description()
+            .registered
+            .swap(true, Ordering::Relaxed)
+        {
+            registry.push(metric);
+        }
+    }
+}
+
+/// Render all the registered metrics in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut metrics = REGISTRY.lock().unwrap().clone();
+    // Keep the series of the same metric together, so HELP and TYPE are written once
+    metrics.sort_by_key(|metric| metric.description().name);
+
+    let mut output = String::new();
+    let mut previous_name = "";
+    for metric in metrics {
+        let description = metric.description();
+        if description.name != previous_name {
+            let _ = writeln!(output, "# HELP {} {}", description.name, description.help);
+            let _ = writeln!(output, "# TYPE {} {}", description.name, metric.kind());
+            previous_name = description.name;
+        }
+        output.push_str(description.name);
+        if !description.labels.is_empty() {
+            let labels: Vec<String> = description
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{key}=\"{value}\""))
+                .collect();
+            let _ = write!(output, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(output, " {}", metric.value());
+    }
+    output
+}
+
+/// Bind the HTTP metrics endpoint and serve it on a background task.
+///
+/// Returns the address it is listening to.
+pub async fn serve(address: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+    register(&[
+        &CONNECTIONS_ACTIVE,
+        &CONNECTIONS_TOTAL,
+        &CONNECTIONS_REJECTED,
+        &BYTES_RECEIVED,
+        &BYTES_SENT,
+        &FRAMES_PARSED,
+        &PARSE_ERRORS,
+    ]);
+
+    let listener = TcpListener::bind(address).await?;
+    let local_address = listener.local_addr()?;
+    info!(address = %local_address, "Serving metrics");
+    tokio::spawn(async move {
+        loop {
+            let (socket, address) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(err) => {
+                    warn!("Failed to accept metrics connection: {err}");
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(err) = handle_http_request(socket).await {
+                    debug!(%address, "Metrics request failed: {err}");
+                }
+            });
+        }
+    });
+    Ok(local_address)
+}
+
+/// Answer a single HTTP request and close the connection.
+async fn handle_http_request(mut socket: TcpStream) -> io::Result<()> {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_http_request(&mut socket)).await
+    {
+        Ok(request) => request?,
+        Err(_) => return Err(io::Error::new(io::ErrorKind::TimedOut, "request timed out")),
+    };
+    let Some(request) = request else {
+        return Ok(());
+    };
+
+    let request_line = String::from_utf8_lossy(&request);
+    let request_line = request_line.lines().next().unwrap_or_default();
+    let response = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = render();
+            format!(
+                "HTTP/1.1 200 OK\r\n\
+                Content-Type: text/plain; version=0.0.4\r\n\
+                Content-Length: {}\r\n\
+                Connection: close\r\n\r\n{body}",
+                body.len()
+            )
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// Read up to the end of the request headers, or `None` if the client hung up or sent too much.
+async fn read_http_request(socket: &mut TcpStream) -> io::Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let len = socket.read(&mut buffer).await?;
+        if len == 0 || request.len() > 8192 {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..len]);
+    }
+    Ok(Some(request))
+}
+
+/// Name, help and labels of a metric.
+pub struct Description {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [(&'static str, &'static str)],
+    registered: AtomicBool,
+}
+
+impl Description {
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            registered: AtomicBool::new(false),
+        }
+    }
+}
+
+pub trait Metric: Sync {
+    fn description(&self) -> &Description;
+    fn kind(&self) -> &'static str;
+    fn value(&self) -> i64;
+}
+
+/// Value that only goes up.
+pub struct Counter {
+    description: Description,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self::with_labels(name, help, &[])
+    }
+
+    /// Series of the metric `name` identified by `labels`.
+    ///
+    /// All the series of a metric must share the same help.
+    pub const fn with_labels(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [(&'static str, &'static str)],
+    ) -> Self {
+        Self {
+            description: Description::new(name, help, labels),
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.inc_by(1);
+    }
+
+    pub fn inc_by(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Counter {
+    fn description(&self) -> &Description {
+        &self.description
+    }
+
+    fn kind(&self) -> &'static str {
+        "counter"
+    }
+
+    fn value(&self) -> i64 {
+        self.get() as i64
+    }
+}
+
+/// Value that can go up and down.
+pub struct Gauge {
+    description: Description,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            description: Description::new(name, help, &[]),
+            value: AtomicI64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+impl Metric for Gauge {
+    fn description(&self) -> &Description {
+        &self.description
+    }
+
+    fn kind(&self) -> &'static str {
+        "gauge"
+    }
+
+    fn value(&self) -> i64 {
+        self.get()
+    }
+}
+
+/// Stream wrapper adding the bytes read and written to [`BYTES_RECEIVED`] and [`BYTES_SENT`].
+#[pin_project]
+pub struct Metered<S> {
+    #[pin]
+    inner: S,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for Metered<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let previous_length = buf.filled().len();
+        let result = self.project().inner.poll_read(cx, buf);
+        BYTES_RECEIVED.inc_by((buf.filled().len() - previous_length) as u64);
+        result
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for Metered<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = self.project().inner.poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            BYTES_SENT.inc_by(written as u64);
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static REQUESTS_OK: Counter =
+        Counter::with_labels("test_requests_total", "Requests", &[("result", "ok")]);
+    static REQUESTS_ERROR: Counter =
+        Counter::with_labels("test_requests_total", "Requests", &[("result", "error")]);
+    static IN_FLIGHT: Gauge = Gauge::new("test_in_flight", "Requests in flight");
+
+    #[test]
+    fn test_render() {
+        register(&[&REQUESTS_OK, &REQUESTS_ERROR, &IN_FLIGHT]);
+        // Registering twice must not duplicate the series
+        register(&[&REQUESTS_OK]);
+
+        REQUESTS_OK.inc_by(3);
+        REQUESTS_ERROR.inc();
+        IN_FLIGHT.inc();
+        IN_FLIGHT.inc();
+        IN_FLIGHT.dec();
+
+        let output = render();
+        assert!(output.contains(
+            "# HELP test_requests_total Requests\n\
+            # TYPE test_requests_total counter\n\
+            test_requests_total{result=\"ok\"} 3\n\
+            test_requests_total{result=\"error\"} 1\n"
+        ));
+        assert!(output.contains(
+            "# HELP test_in_flight Requests in flight\n\
+            # TYPE test_in_flight gauge\n\
+            test_in_flight 1\n"
+        ));
+    }
+}
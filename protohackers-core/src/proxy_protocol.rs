@@ -0,0 +1,190 @@
+//! PROXY protocol v1 and v2 headers, sent by load balancers like HAProxy before the client data.
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Longest v1 header, CRLF included
+const V1_MAX_LENGTH: usize = 107;
+
+/// Read the PROXY header at the start of `stream`, leaving the client data after it unread.
+///
+/// Returns the address of the client, or `None` when the header doesn't carry one, like
+/// health checks from the load balancer itself. Those connections should keep using the peer
+/// address of the socket.
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // Shortest v1 header is "PROXY UNKNOWN\r\n", so this never reads past the header
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut header = [0; 4];
+        stream.read_exact(&mut header).await?;
+        let [version_command, family, length @ ..] = header;
+        let mut addresses = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut addresses).await?;
+        parse_v2(version_command, family, &addresses)
+    } else if start.starts_with(b"PROXY ") {
+        let mut line = start.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() == V1_MAX_LENGTH {
+                return Err(invalid("v1 header is too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        parse_v1(&line)
+    } else {
+        Err(invalid("missing header"))
+    }
+}
+
+/// Parse a human readable header, like `PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n`.
+pub fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let line = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| invalid("v1 header must end with CRLF"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _destination, source_port, _destination_port] =>
+        {
+            let ip: IpAddr = source
+                .parse()
+                .map_err(|_| invalid("v1 header has an invalid source address"))?;
+            if ip.is_ipv4() != (family == "TCP4") {
+                return Err(invalid("v1 header address doesn't match its family"));
+            }
+            let port = source_port
+                .parse()
+                .map_err(|_| invalid("v1 header has an invalid source port"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+/// Parse the binary header that follows the v2 signature.
+pub fn parse_v2(
+    version_command: u8,
+    family: u8,
+    addresses: &[u8],
+) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL: the balancer talking for itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    // Addresses are followed by optional TLVs, which are ignored
+    match family {
+        // TCP or UDP over IPv4
+        0x11 | 0x12 => {
+            let Some(addresses) = addresses.get(..12) else {
+                return Err(invalid("v2 IPv4 addresses are truncated"));
+            };
+            let ip = Ipv4Addr::from(<[u8; 4]>::try_from(&addresses[..4]).unwrap());
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // TCP or UDP over IPv6
+        0x21 | 0x22 => {
+            let Some(addresses) = addresses.get(..36) else {
+                return Err(invalid("v2 IPv6 addresses are truncated"));
+            };
+            let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&addresses[..16]).unwrap());
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // Unspecified or Unix sockets: nothing useful to log or limit on
+        _ => Ok(None),
+    }
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid PROXY protocol header: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_v1() {
+        let mut stream: &[u8] = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nhello";
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+        // The client data is left for the connection handler
+        assert_eq!(stream, b"hello");
+
+        let mut stream: &[u8] = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 7\r\n";
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some("[2001:db8::1]:4000".parse().unwrap())
+        );
+
+        let mut stream: &[u8] = b"PROXY UNKNOWN\r\n";
+        assert_eq!(read_header(&mut stream).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2, 0x1f, 0x90, 0, 7]);
+        header.extend_from_slice(b"hello");
+
+        let mut stream = &header[..];
+        assert_eq!(
+            read_header(&mut stream).await.unwrap(),
+            Some("10.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(stream, b"hello");
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(read_header(&mut &local[..]).await.unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_headers() {
+        for line in [
+            &b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 4000 7\r\n",
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 99999 443\r\n",
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\n",
+        ] {
+            assert!(parse_v1(line).is_err(), "{line:?}");
+        }
+
+        assert!(parse_v2(0x11, 0x11, &[0; 12]).is_err());
+        assert!(parse_v2(0x21, 0x11, &[0; 8]).is_err());
+        assert!(parse_v2(0x21, 0x21, &[0; 12]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_header() {
+        let mut stream: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+        assert!(read_header(&mut stream).await.is_err());
+
+        let mut stream = &[b"PROXY ".as_slice(), &[b'1'; 200]].concat()[..];
+        assert!(read_header(&mut stream).await.is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::limits::{ConnectionLimiter, Rejection};
+use crate::metrics::{CONNECTIONS_ACTIVE, CONNECTIONS_REJECTED, CONNECTIONS_TOTAL};
+use crate::proxy_protocol;
+use crate::shutdown::Shutdown;
+
+type RejectHandler = dyn Fn(TcpStream, Rejection) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send>>
+    + Send
+    + Sync;
+
+/// How long a rejected client is given to receive the reason before being disconnected.
+const REJECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a load balancer has to send the PROXY protocol header.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tunables of the accept loop.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// How long to wait for open connections to finish once a shutdown was triggered.
+    /// Connections still running after that are aborted.
+    pub drain_timeout: Duration,
+    /// Maximum number of connections open at the same time
+    pub max_connections: Option<usize>,
+    /// Maximum number of connections open at the same time from a single IP address
+    pub max_connections_per_ip: Option<usize>,
+    /// Maximum number of new connections per second from a single IP address
+    pub accept_rate_per_ip: Option<u32>,
+    /// How long a connection may go without receiving anything before it is closed.
+    /// Each protocol decides what counts as activity.
+    pub idle_timeout: Option<Duration>,
+    /// How long a client has to complete the protocol handshake, like picking a username
+    pub handshake_timeout: Option<Duration>,
+    /// Expect a PROXY protocol header at the start of every connection and use the client
+    /// address it carries, instead of the load balancer's, for the limits and the handler.
+    /// Connections still sending their header count towards `max_connections`.
+    pub proxy_protocol: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            drain_timeout: Duration::from_secs(5),
+            max_connections: None,
+            max_connections_per_ip: None,
+            accept_rate_per_ip: None,
+            idle_timeout: None,
+            handshake_timeout: None,
+            proxy_protocol: false,
+        }
+    }
+}
+
+/// TCP accept loop shared by all the servers.
+///
+/// Every accepted socket is handed to the connection handler on its own task.
+pub struct Server {
+    listener: TcpListener,
+    config: Config,
+    shutdown: Shutdown,
+    reject_handler: Option<Arc<RejectHandler>>,
+}
+
+impl Server {
+    pub async fn bind(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self::new(TcpListener::bind(address).await?))
+    }
+
+    pub fn new(listener: TcpListener) -> Self {
+        Self {
+            listener,
+            config: Config::default(),
+            shutdown: Shutdown::new(),
+            reject_handler: None,
+        }
+    }
+
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Stop this server together with the others sharing `shutdown`.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Tell clients refused by the connection limits why, for protocols that have an
+    /// error message. By default they are disconnected right away.
+    pub fn with_reject_handler<R, F>(mut self, handler: R) -> Self
+    where
+        R: Fn(TcpStream, Rejection) -> F + Send + Sync + 'static,
+        F: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        self.reject_handler = Some(Arc::new(move |socket, rejection| {
+            Box::pin(handler(socket, rejection))
+        }));
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns the local address that the Server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr().unwrap()
+    }
+
+    /// Handle to stop this server.
+    ///
+    /// Connection handlers can also watch it to say goodbye to their clients before the
+    /// drain timeout expires.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Accept connections until a shutdown is triggered, spawning `handler` for each one of them.
+    ///
+    /// Errors returned by the handler are logged and close that connection only.
+    /// Connections over the limits in [`Config`] are refused before reaching the handler.
+    /// With [`Config::proxy_protocol`], both see the client address from the PROXY header.
+    /// Once the shutdown is triggered, no new connections are accepted and this waits up to
+    /// [`Config::drain_timeout`] for the open ones to finish.
+    pub async fn run<H, F>(self, handler: H)
+    where
+        H: Fn(TcpStream, SocketAddr) -> F + Send + Sync + 'static,
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let mut limiter = ConnectionLimiter::new(&self.config);
+        let mut connections = JoinSet::new();
+        // Connections waiting for their PROXY protocol header
+        let mut proxied = JoinSet::new();
+
+        loop {
+            let (socket, address, reservation) = tokio::select! {
+                _ = self.shutdown.wait() => break,
+                // Reap finished connections so the set doesn't grow forever
+                Some(_) = connections.join_next() => continue,
+                Some(header) = proxied.join_next() => match header {
+                    Ok(Some((socket, address, reservation))) => (socket, address, Some(reservation)),
+                    // Failed or panicked reading the header. Already logged.
+                    _ => continue,
+                },
+                accepted = self.listener.accept() => match accepted {
+                    Ok((socket, address)) if self.config.proxy_protocol => {
+                        // Count the connection in `max_connections` while it sends its header,
+                        // so that silent ones can't pile up
+                        match limiter.reserve() {
+                            Ok(reservation) => {
+                                proxied.spawn(async move {
+                                    let (socket, address) = read_proxy_header(socket, address).await?;
+                                    Some((socket, address, reservation))
+                                });
+                            }
+                            Err(rejection) => {
+                                warn!(peer = %address, "Connection refused: {rejection}");
+                                CONNECTIONS_REJECTED.inc();
+                                self.reject(socket, address, rejection);
+                            }
+                        }
+                        continue;
+                    }
+                    Ok((socket, address)) => (socket, address, None),
+                    Err(err) => {
+                        // Accept errors, like running out of file descriptors, are specific to
+                        // that connection. Keep serving the others.
+                        warn!("Failed to accept connection: {err}");
+                        continue;
+                    }
+                },
+            };
+            let admitted = match reservation {
+                Some(reservation) => limiter.admit_reserved(address.ip(), reservation),
+                None => limiter.admit(address.ip()),
+            };
+            let permit = match admitted {
+                Ok(permit) => permit,
+                Err(rejection) => {
+                    warn!(%address, "Connection refused: {rejection}");
+                    CONNECTIONS_REJECTED.inc();
+                    self.reject(socket, address, rejection);
+                    continue;
+                }
+            };
+            info!(%address, "New connection");
+            CONNECTIONS_TOTAL.inc();
+
+            let handler = Arc::clone(&handler);
+            let active = ActiveConnection::new();
+            connections.spawn(async move {
+                let _permit = permit;
+                let _active = active;
+                if let Err(err) = handler(socket, address).await {
+                    error!(%address, "Connection closed: {err:#}");
+                } else {
+                    info!(%address, "Connection closed");
+                }
+            });
+        }
+
+        // Stop accepting new connections right away
+        drop(self.listener);
+        info!(
+            connections = connections.len(),
+            "Shutting down. Waiting for open connections to finish"
+        );
+        let drain = async { while connections.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.config.drain_timeout, drain)
+            .await
+            .is_err()
+        {
+            warn!(
+                connections = connections.len(),
+                "Drain timeout reached. Aborting open connections"
+            );
+            connections.shutdown().await;
+        }
+        info!("Server stopped");
+    }
+
+    fn reject(&self, socket: TcpStream, address: SocketAddr, rejection: Rejection) {
+        let Some(reject_handler) = &self.reject_handler else {
+            // Dropping the socket closes the connection
+            return;
+        };
+        let reject = reject_handler(socket, rejection);
+        tokio::spawn(async move {
+            match tokio::time::timeout(REJECT_TIMEOUT, reject).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => debug!(%address, "Could not notify refused client: {err}"),
+                Err(_) => debug!(%address, "Timed out notifying refused client"),
+            }
+        });
+    }
+}
+
+/// Replace the peer address of a proxied connection by the client address in its header.
+///
+/// Returns `None` when the connection must be closed.
+async fn read_proxy_header(
+    mut socket: TcpStream,
+    peer: SocketAddr,
+) -> Option<(TcpStream, SocketAddr)> {
+    match tokio::time::timeout(
+        PROXY_HEADER_TIMEOUT,
+        proxy_protocol::read_header(&mut socket),
+    )
+    .await
+    {
+        Ok(Ok(client)) => {
+            let address = client.unwrap_or(peer);
+            debug!(%peer, %address, "Read PROXY protocol header");
+            Some((socket, address))
+        }
+        Ok(Err(err)) => {
+            warn!(%peer, "Connection refused: {err}");
+            None
+        }
+        Err(_) => {
+            warn!(%peer, "Connection refused: timed out waiting for the PROXY protocol header");
+            None
+        }
+    }
+}
+
+/// Counts the connection in [`CONNECTIONS_ACTIVE`] until dropped, even if its task is aborted.
+struct ActiveConnection;
+
+impl ActiveConnection {
+    fn new() -> Self {
+        CONNECTIONS_ACTIVE.inc();
+        Self
+    }
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        CONNECTIONS_ACTIVE.dec();
+    }
+}
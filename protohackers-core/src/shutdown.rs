@@ -0,0 +1,77 @@
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Handle used to stop a server and to let its connections know it is going away.
+///
+/// Clones share the same state: triggering any of them notifies all the others.
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown(CancellationToken);
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the server, and every connection watching this handle, to stop.
+    pub fn trigger(&self) {
+        self.0.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Completes once the shutdown was triggered.
+    ///
+    /// # Cancel safety
+    /// This is cancel safe.
+    pub async fn wait(&self) {
+        self.0.cancelled().await
+    }
+
+    /// Trigger the shutdown when the process receives SIGINT or SIGTERM.
+    pub fn trigger_on_signal(&self) {
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            match wait_for_signal().await {
+                Ok(signal) => info!("Received {signal}. Shutting down"),
+                Err(err) => warn!("Could not listen for signals, shutting down: {err}"),
+            }
+            shutdown.trigger();
+        });
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+        _ = sigterm.recv() => Ok("SIGTERM"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await.map(|_| "Ctrl-C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_trigger_notifies_clones() {
+        let shutdown = Shutdown::new();
+        let clone = shutdown.clone();
+        assert!(!clone.is_triggered());
+
+        let waiter = tokio::spawn(async move { clone.wait().await });
+        shutdown.trigger();
+
+        waiter.await.unwrap();
+        assert!(shutdown.is_triggered());
+    }
+}
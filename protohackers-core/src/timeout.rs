@@ -0,0 +1,45 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::time::Duration;
+
+/// A connection did not make progress within its deadline.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TimedOut;
+
+impl Display for TimedOut {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Like [`tokio::time::timeout`], but waits forever when `duration` is `None`.
+pub async fn timeout<F: Future>(
+    duration: Option<Duration>,
+    future: F,
+) -> Result<F::Output, TimedOut> {
+    match duration {
+        None => Ok(future.await),
+        Some(duration) => tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| TimedOut),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_timeout() {
+        let slow = tokio::time::sleep(Duration::from_secs(10));
+        assert_eq!(
+            timeout(Some(Duration::from_secs(1)), slow).await,
+            Err(TimedOut)
+        );
+
+        let slow = tokio::time::sleep(Duration::from_secs(10));
+        assert_eq!(timeout(None, slow).await, Ok(()));
+    }
+}
@@ -0,0 +1,47 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use protohackers_core::metrics;
+
+async fn get(address: SocketAddr, path: &str) -> String {
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    connection.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    connection.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint() {
+    let address = metrics::serve("127.0.0.1:0").await.unwrap();
+
+    let response = get(address, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("# TYPE protohackers_connections_active gauge\n"));
+    assert!(response.contains("\nprotohackers_bytes_received_total "));
+
+    let response = get(address, "/").await;
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_idle_metrics_connection_is_closed() {
+    let address = metrics::serve("127.0.0.1:0").await.unwrap();
+
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    let mut response = Vec::new();
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        connection.read_to_end(&mut response),
+    )
+    .await
+    .expect("Idle metrics connection was not closed")
+    .unwrap();
+    assert!(response.is_empty());
+}
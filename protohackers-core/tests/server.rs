@@ -0,0 +1,247 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::bail;
+use tokio::io::{copy, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use protohackers_core::limits::Rejection;
+use protohackers_core::server::{Config, Server};
+
+async fn start_echo_server() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr();
+
+    tokio::spawn(server.run(|mut socket: TcpStream, _address| async move {
+        let (mut reader, mut writer) = socket.split();
+        let copied = copy(&mut reader, &mut writer).await?;
+        if copied == 0 {
+            bail!("Nothing to echo");
+        }
+        Ok(())
+    }));
+
+    address
+}
+
+#[tokio::test]
+async fn test_concurrent_connections() {
+    let server = start_echo_server().await;
+    let mut connection1 = TcpStream::connect(server).await.unwrap();
+    let mut connection2 = TcpStream::connect(server).await.unwrap();
+
+    connection2.write_all(b"world").await.unwrap();
+    connection1.write_all(b"hello").await.unwrap();
+
+    let mut response = [0u8; 5];
+    connection1.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"hello");
+    connection2.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"world");
+}
+
+#[tokio::test]
+async fn test_handler_error_keeps_serving() {
+    let server = start_echo_server().await;
+
+    let connection = TcpStream::connect(server).await.unwrap();
+    drop(connection);
+
+    let mut connection = TcpStream::connect(server).await.unwrap();
+    connection.write_all(b"ping").await.unwrap();
+    let mut response = [0u8; 4];
+    connection.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"ping");
+}
+
+#[tokio::test]
+async fn test_shutdown_drains_connections() {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let address = server.local_addr();
+    let shutdown = server.shutdown_handle();
+
+    let connection_shutdown = shutdown.clone();
+    let server = tokio::spawn(server.run(move |mut socket: TcpStream, _address| {
+        let shutdown = connection_shutdown.clone();
+        async move {
+            shutdown.wait().await;
+            socket.write_all(b"bye").await?;
+            Ok(())
+        }
+    }));
+
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    // Make sure the connection was accepted before shutting down
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown.trigger();
+
+    let mut response = Vec::new();
+    connection.read_to_end(&mut response).await.unwrap();
+    assert_eq!(&response, b"bye");
+
+    server.await.unwrap();
+    assert!(TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn test_shutdown_aborts_after_drain_timeout() {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(Config {
+            drain_timeout: Duration::from_millis(100),
+            ..Config::default()
+        });
+    let address = server.local_addr();
+    let shutdown = server.shutdown_handle();
+
+    let server = tokio::spawn(server.run(|_socket: TcpStream, _address| async move {
+        // Never finishes on its own
+        std::future::pending::<()>().await;
+        Ok(())
+    }));
+
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    shutdown.trigger();
+
+    tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("Server did not stop after the drain timeout")
+        .unwrap();
+    let mut response = Vec::new();
+    connection.read_to_end(&mut response).await.unwrap();
+    assert!(response.is_empty());
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(Config {
+            max_connections: Some(1),
+            ..Config::default()
+        })
+        .with_reject_handler(|mut socket: TcpStream, rejection| async move {
+            socket.write_all(rejection.to_string().as_bytes()).await
+        });
+    let address = server.local_addr();
+    tokio::spawn(server.run(|mut socket: TcpStream, _address| async move {
+        let (mut reader, mut writer) = socket.split();
+        copy(&mut reader, &mut writer).await?;
+        Ok(())
+    }));
+
+    let mut connection1 = TcpStream::connect(address).await.unwrap();
+    connection1.write_all(b"ping").await.unwrap();
+    let mut response = [0u8; 4];
+    connection1.read_exact(&mut response).await.unwrap();
+
+    let mut connection2 = TcpStream::connect(address).await.unwrap();
+    let mut response = String::new();
+    connection2.read_to_string(&mut response).await.unwrap();
+    assert_eq!(response, Rejection::TooManyConnections.to_string());
+
+    // The slot is given back once the first connection is closed
+    drop(connection1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut connection3 = TcpStream::connect(address).await.unwrap();
+    connection3.write_all(b"pong").await.unwrap();
+    let mut response = [0u8; 4];
+    connection3.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"pong");
+}
+
+#[tokio::test]
+async fn test_proxy_protocol() {
+    let config = Config {
+        max_connections_per_ip: Some(1),
+        proxy_protocol: true,
+        ..Config::default()
+    };
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(config);
+    let address = server.local_addr();
+    tokio::spawn(server.run(|mut socket: TcpStream, address| async move {
+        // Tell the client which address the server sees, then echo
+        socket.write_all(format!("{address}\n").as_bytes()).await?;
+        let (mut reader, mut writer) = socket.split();
+        copy(&mut reader, &mut writer).await?;
+        Ok(())
+    }));
+
+    // Both come from the same balancer, but from different clients behind it
+    let mut clients = Vec::new();
+    for (header, client) in [
+        (
+            "PROXY TCP4 203.0.113.7 127.0.0.1 40000 7\r\n",
+            "203.0.113.7:40000",
+        ),
+        (
+            "PROXY TCP4 203.0.113.8 127.0.0.1 40001 7\r\n",
+            "203.0.113.8:40001",
+        ),
+    ] {
+        let mut connection = TcpStream::connect(address).await.unwrap();
+        connection.write_all(header.as_bytes()).await.unwrap();
+        connection.write_all(b"ping").await.unwrap();
+
+        let mut response = vec![0u8; client.len() + 1 + 4];
+        connection.read_exact(&mut response).await.unwrap();
+        assert_eq!(response, format!("{client}\nping").as_bytes());
+        clients.push(connection);
+    }
+
+    // Connections without a header are refused
+    let mut connection = TcpStream::connect(address).await.unwrap();
+    connection
+        .write_all(b"no header here, just data")
+        .await
+        .unwrap();
+    // The unread data may turn the close into a reset
+    let mut response = Vec::new();
+    let read = connection.read_to_end(&mut response).await;
+    assert!(matches!(read, Ok(0) | Err(_)), "{read:?}");
+}
+
+#[tokio::test]
+async fn test_proxy_protocol_header_counts_towards_max_connections() {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_config(Config {
+            max_connections: Some(1),
+            proxy_protocol: true,
+            ..Config::default()
+        })
+        .with_reject_handler(|mut socket: TcpStream, rejection| async move {
+            socket.write_all(rejection.to_string().as_bytes()).await
+        });
+    let address = server.local_addr();
+    tokio::spawn(server.run(|mut socket: TcpStream, _address| async move {
+        let (mut reader, mut writer) = socket.split();
+        copy(&mut reader, &mut writer).await?;
+        Ok(())
+    }));
+
+    // Still silent, yet holding the only slot
+    let mut connection1 = TcpStream::connect(address).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut connection2 = TcpStream::connect(address).await.unwrap();
+    let mut response = String::new();
+    connection2.read_to_string(&mut response).await.unwrap();
+    assert_eq!(response, Rejection::TooManyConnections.to_string());
+
+    // The slot carries over to the connection once its header arrives
+    connection1
+        .write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 40000 7\r\nping")
+        .await
+        .unwrap();
+    let mut response = [0u8; 4];
+    connection1.read_exact(&mut response).await.unwrap();
+    assert_eq!(&response, b"ping");
+}